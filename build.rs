@@ -1,4 +1,5 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
         tonic_build::compile_protos("proto/rust_checker.proto")?;
+        tonic_build::compile_protos("proto/health.proto")?;
             Ok(())
 }
@@ -0,0 +1,211 @@
+extern crate pnet;
+
+use log::{debug, error};
+use crate::config::{ProbeConfig, get_duration, get_strings};
+use crate::resolver::Resolver;
+use crate::scheduler::Scheduler;
+use crate::interface::source_ipv4s;
+use yaml_rust::Yaml;
+use std::net::{IpAddr, Ipv4Addr};
+use std::process;
+use std::time::{Duration, Instant};
+use std::sync::mpsc::Sender;
+use pnet::datalink::{self, Channel, DataLinkReceiver, MacAddr, NetworkInterface};
+use pnet::packet::Packet;
+use pnet::packet::arp::{ArpHardwareTypes, ArpOperations, ArpPacket, MutableArpPacket};
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
+use std::collections::HashMap;
+use crate::checker::CheckResult;
+
+pub struct ArpChecker {
+    resolver: Resolver,
+    interface: String,
+    scheduler: Scheduler,
+    timeout: Duration,
+    source_ip: Vec<Ipv4Addr>,
+    name: String,
+    precision: i64,
+    labels: HashMap<String, String>
+}
+
+impl ArpChecker {
+    pub fn new(config: &ProbeConfig) -> Self {
+        Self{
+            name: config.name.clone(),
+            resolver: Resolver::new(config, format!("{}:0", config.host)),
+            interface: match config.config.get("interface").and_then(|i| i.as_str()) {
+                Some(interface) => String::from(interface),
+                None => {
+                    error!("Interface is required for {}", config.name);
+                    process::exit(1);
+                }
+            },
+            scheduler: Scheduler::new(config),
+            timeout: get_duration(&config.config, "timeout", Duration::from_secs(1)),
+            source_ip: match source_ipv4s(&get_strings(&config.config, "source_ip")) {
+                Ok(sources) => sources,
+                Err(source_ip) => {
+                    error!("Invalid source_ip {} for {}", source_ip, config.name);
+                    process::exit(1);
+                }
+            },
+            precision: config.config.get("precision")
+                .unwrap_or(&Yaml::Integer(1))
+                .clone()
+                .into_i64()
+                .unwrap(),
+            labels: config.labels.clone()
+        }
+    }
+}
+
+fn arp_request(interface: &NetworkInterface, source: Ipv4Addr, target: Ipv4Addr) -> Vec<u8> {
+    let mut ethernet_buffer = vec![0; EthernetPacket::minimum_packet_size() + ArpPacket::minimum_packet_size()];
+    let mut arp_buffer = vec![0; ArpPacket::minimum_packet_size()];
+    let source_mac = interface.mac.unwrap_or(MacAddr::zero());
+    let mut arp = MutableArpPacket::new(&mut arp_buffer[..]).unwrap();
+    arp.set_hardware_type(ArpHardwareTypes::Ethernet);
+    arp.set_protocol_type(EtherTypes::Ipv4);
+    arp.set_hw_addr_len(6);
+    arp.set_proto_addr_len(4);
+    arp.set_operation(ArpOperations::Request);
+    arp.set_sender_hw_addr(source_mac);
+    arp.set_sender_proto_addr(source);
+    arp.set_target_hw_addr(MacAddr::zero());
+    arp.set_target_proto_addr(target);
+    let mut ethernet = MutableEthernetPacket::new(&mut ethernet_buffer[..]).unwrap();
+    ethernet.set_destination(MacAddr::broadcast());
+    ethernet.set_source(source_mac);
+    ethernet.set_ethertype(EtherTypes::Arp);
+    ethernet.set_payload(&arp_buffer[..]);
+    ethernet_buffer
+}
+
+// Replies that arrived after the timeout of a previous round would be matched by the next one
+fn drain(rx: &mut Box<dyn DataLinkReceiver>, timeout: Duration) {
+    let start = Instant::now();
+    while Instant::now().duration_since(start) < timeout && rx.next().is_ok() {}
+}
+
+pub fn arp_pinger(mut checker: ArpChecker, sender: Sender<CheckResult>) {
+    let interface = match datalink::interfaces().into_iter().find(|i| i.name == checker.interface) {
+        Some(i) => i,
+        None => {
+            error!("Interface {} not found", checker.interface);
+            return;
+        }
+    };
+    let saddrs = if checker.source_ip.is_empty() {
+        match interface.ips.iter().find(|ip| ip.is_ipv4()).map(|ip| ip.ip()) {
+            Some(IpAddr::V4(ip)) => vec![ip],
+            _ => {
+                error!("No IPv4 address on {}", checker.interface);
+                return;
+            }
+        }
+    } else {
+        checker.source_ip.clone()
+    };
+    let timeout = checker.timeout;
+    let read_timeout = Duration::from_millis(100);
+    let mac = interface.mac.unwrap_or(MacAddr::zero());
+    let config = datalink::Config {
+        read_timeout: Some(read_timeout),
+        promiscuous: false,
+        ..Default::default()
+    };
+    let (mut tx, mut rx) = match datalink::channel(&interface, config) {
+        Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
+        Ok(_) => {
+            error!("Unsupported channel type on {}", checker.interface);
+            return;
+        },
+        Err(e) => {
+            error!("Failed to open {}: {}", checker.interface, e);
+            return;
+        }
+    };
+    loop {
+        checker.scheduler.wait();
+        checker.resolver.resolve_or_report(&checker.name, &checker.labels, &sender);
+        drain(&mut rx, read_timeout);
+        let targets = checker.resolver.addresses()
+            .into_iter()
+            .flat_map(|resolved| saddrs.iter().map(move |saddr| (resolved, *saddr)))
+            .collect::<Vec<_>>();
+        for (resolved, saddr) in targets {
+            let addr = match resolved.ip() {
+                IpAddr::V4(ip) => ip,
+                IpAddr::V6(_) => continue
+            };
+            let request = arp_request(&interface, saddr, addr);
+            let start = Instant::now();
+            let mut reply = None;
+            match tx.send_to(&request, None) {
+                Some(Ok(_)) => {
+                    while reply.is_none() && Instant::now().duration_since(start) < timeout {
+                        match rx.next() {
+                            Ok(frame) => {
+                                let now = Instant::now();
+                                let ethernet = match EthernetPacket::new(frame) {
+                                    Some(e) if e.get_ethertype() == EtherTypes::Arp => e,
+                                    _ => continue
+                                };
+                                if let Some(arp) = ArpPacket::new(ethernet.payload()) {
+                                    if arp.get_operation() == ArpOperations::Reply
+                                        && arp.get_sender_proto_addr() == addr
+                                        && arp.get_target_hw_addr() == mac
+                                        && arp.get_target_proto_addr() == saddr {
+                                        reply = Some((now, arp.get_sender_hw_addr()));
+                                    }
+                                }
+                            },
+                            Err(e) => {
+                                if e.kind() != std::io::ErrorKind::TimedOut {
+                                    debug!("Error getting packet {:?}", e);
+                                }
+                            }
+                        }
+                    }
+                },
+                _ => {
+                    debug!("Failed to send arp request");
+                }
+            }
+            let mut labels = checker.resolver.labels(&checker.labels, &resolved);
+            labels.insert(String::from("source"), saddr.to_string());
+            match reply {
+                Some((received, mac)) => {
+                    // mac is on rtt only, so loss stays one series with and without a reply
+                    let mut rtt_labels = labels.clone();
+                    rtt_labels.insert(String::from("mac"), mac.to_string());
+                    let mut to_emit = CheckResult{
+                        name: checker.name.clone(),
+                        values: HashMap::new(),
+                        processes: Vec::new(),
+                        labels: rtt_labels};
+                    to_emit.values.insert(
+                        String::from("rtt"),
+                        (received.duration_since(start).as_micros() as f32) / checker.precision as f32);
+                    sender.send(to_emit).unwrap();
+                    let mut to_emit = CheckResult{
+                        name: checker.name.clone(),
+                        values: HashMap::new(),
+                        processes: Vec::new(),
+                        labels};
+                    to_emit.values.insert(String::from("loss"), 0.0);
+                    sender.send(to_emit).unwrap();
+                },
+                None => {
+                    let mut to_emit = CheckResult{
+                        name: checker.name.clone(),
+                        values: HashMap::new(),
+                        processes: Vec::new(),
+                        labels};
+                    to_emit.values.insert(String::from("loss"), 1.0);
+                    sender.send(to_emit).unwrap();
+                }
+            }
+        }
+    }
+}
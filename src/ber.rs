@@ -0,0 +1,261 @@
+// Subset of BER used by SNMP: definite lengths, primitive types and constructed sequences
+
+pub const INTEGER: u8 = 0x02;
+pub const OCTET_STRING: u8 = 0x04;
+pub const NULL: u8 = 0x05;
+pub const OBJECT_IDENTIFIER: u8 = 0x06;
+pub const SEQUENCE: u8 = 0x30;
+pub const IP_ADDRESS: u8 = 0x40;
+pub const COUNTER32: u8 = 0x41;
+pub const GAUGE32: u8 = 0x42;
+pub const TIMETICKS: u8 = 0x43;
+pub const COUNTER64: u8 = 0x46;
+pub const NO_SUCH_OBJECT: u8 = 0x80;
+pub const NO_SUCH_INSTANCE: u8 = 0x81;
+pub const END_OF_MIB_VIEW: u8 = 0x82;
+
+pub fn encode(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut result = vec![tag];
+    let length = content.len();
+    if length < 0x80 {
+        result.push(length as u8);
+    } else {
+        let bytes: Vec<u8> = length.to_be_bytes().iter().skip_while(|b| **b == 0).copied().collect();
+        result.push(0x80 | bytes.len() as u8);
+        result.extend(bytes);
+    }
+    result.extend_from_slice(content);
+    result
+}
+
+pub fn encode_sequence(tag: u8, items: &[Vec<u8>]) -> Vec<u8> {
+    encode(tag, &items.concat())
+}
+
+pub fn encode_integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // Shortest two's complement form
+    let mut start = 0;
+    while start < 7 && ((bytes[start] == 0 && bytes[start + 1] & 0x80 == 0)
+        || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0)) {
+        start += 1;
+    }
+    encode(INTEGER, &bytes[start..])
+}
+
+pub fn encode_octets(value: &[u8]) -> Vec<u8> {
+    encode(OCTET_STRING, value)
+}
+
+pub fn encode_null() -> Vec<u8> {
+    encode(NULL, &[])
+}
+
+// First two arcs share the first subidentifier, e.g. 2.999 is encoded as 1079
+pub fn encode_oid(oid: &[u32]) -> Vec<u8> {
+    let mut content = Vec::new();
+    let subidentifiers = match oid {
+        [first, second, rest @ ..] => [&[first * 40 + second][..], rest].concat(),
+        _ => Vec::new()
+    };
+    for arc in subidentifiers {
+        let mut arc = arc;
+        let mut bytes = vec![(arc & 0x7f) as u8];
+        arc >>= 7;
+        while arc > 0 {
+            bytes.push(0x80 | (arc & 0x7f) as u8);
+            arc >>= 7;
+        }
+        bytes.reverse();
+        content.extend(bytes);
+    }
+    encode(OBJECT_IDENTIFIER, &content)
+}
+
+pub fn decode_integer(content: &[u8]) -> Option<i64> {
+    if content.is_empty() || content.len() > 8 {
+        return None;
+    }
+    let mut value: i64 = if content[0] & 0x80 != 0 { -1 } else { 0 };
+    for b in content {
+        value = (value << 8) | *b as i64;
+    }
+    Some(value)
+}
+
+// Counters and gauges are unsigned, Counter64 may have a leading zero byte
+pub fn decode_unsigned(content: &[u8]) -> Option<u64> {
+    if content.is_empty() || content.len() > 9 {
+        return None;
+    }
+    let mut value: u64 = 0;
+    for b in content {
+        if value >> 56 != 0 {
+            return None;
+        }
+        value = (value << 8) | *b as u64;
+    }
+    Some(value)
+}
+
+pub fn decode_oid(content: &[u8]) -> Option<Vec<u32>> {
+    let mut result = Vec::new();
+    let mut arc: u32 = 0;
+    for b in content {
+        if arc >> 25 != 0 {
+            return None;
+        }
+        arc = (arc << 7) | (b & 0x7f) as u32;
+        if b & 0x80 == 0 {
+            if result.is_empty() {
+                let first = (arc / 40).min(2);
+                result.push(first);
+                arc -= first * 40;
+            }
+            result.push(arc);
+            arc = 0;
+        }
+    }
+    // Empty or ending in the middle of a subidentifier
+    if result.is_empty() || content.last()? & 0x80 != 0 {
+        return None;
+    }
+    Some(result)
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self{data}
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    // Next element as tag and content
+    pub fn read(&mut self) -> Option<(u8, &'a [u8])> {
+        let tag = *self.data.first()?;
+        let first = *self.data.get(1)? as usize;
+        let (length, header) = if first < 0x80 {
+            (first, 2)
+        } else {
+            let count = first & 0x7f;
+            if count == 0 || count > 4 {
+                return None;
+            }
+            let mut length = 0;
+            for b in self.data.get(2..2 + count)? {
+                length = (length << 8) | *b as usize;
+            }
+            (length, 2 + count)
+        };
+        let content = self.data.get(header..header + length)?;
+        self.data = &self.data[header + length..];
+        Some((tag, content))
+    }
+
+    pub fn read_tag(&mut self, expected: u8) -> Option<&'a [u8]> {
+        match self.read()? {
+            (tag, content) if tag == expected => Some(content),
+            _ => None
+        }
+    }
+
+    pub fn read_integer(&mut self) -> Option<i64> {
+        decode_integer(self.read_tag(INTEGER)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_round_trip() {
+        for value in [0, 1, -1, 127, 128, -128, -129, 255, 256, 32767, -32768, i32::MAX as i64, i64::MAX, i64::MIN] {
+            let encoded = encode_integer(value);
+            let mut reader = Reader::new(&encoded);
+            assert_eq!(reader.read_integer(), Some(value), "{}", value);
+            assert!(reader.is_empty());
+        }
+    }
+
+    #[test]
+    fn integer_shortest_form() {
+        assert_eq!(encode_integer(0), [INTEGER, 1, 0x00]);
+        assert_eq!(encode_integer(127), [INTEGER, 1, 0x7f]);
+        assert_eq!(encode_integer(128), [INTEGER, 2, 0x00, 0x80]);
+        assert_eq!(encode_integer(-128), [INTEGER, 1, 0x80]);
+        assert_eq!(encode_integer(-129), [INTEGER, 2, 0xff, 0x7f]);
+        assert_eq!(decode_integer(&[]), None);
+        assert_eq!(decode_integer(&[1; 9]), None);
+    }
+
+    #[test]
+    fn oid_round_trip() {
+        let oids: [&[u32]; 6] = [
+            &[1, 3, 6, 1, 2, 1, 1, 3, 0],
+            &[1, 3, 6, 1, 4, 1, 2021, 10, 1, 3, 1],
+            &[0, 0],
+            &[1, 39, 127, 128, 16383, 16384],
+            &[2, 999, 3],
+            &[1, 3, u32::MAX]];
+        for oid in oids {
+            let encoded = encode_oid(oid);
+            let mut reader = Reader::new(&encoded);
+            let content = reader.read_tag(OBJECT_IDENTIFIER).unwrap();
+            assert_eq!(decode_oid(content).as_deref(), Some(oid), "{:?}", oid);
+        }
+    }
+
+    #[test]
+    fn oid_encoding() {
+        assert_eq!(encode_oid(&[1, 3, 6, 1, 2, 1]), [OBJECT_IDENTIFIER, 5, 0x2b, 6, 1, 2, 1]);
+        assert_eq!(encode_oid(&[1, 3, 128]), [OBJECT_IDENTIFIER, 3, 0x2b, 0x81, 0x00]);
+        assert_eq!(encode_oid(&[2, 999]), [OBJECT_IDENTIFIER, 2, 0x88, 0x37]);
+    }
+
+    #[test]
+    fn oid_invalid() {
+        assert_eq!(decode_oid(&[]), None);
+        // Truncated subidentifier
+        assert_eq!(decode_oid(&[0x2b, 0x81]), None);
+        // Arc above 2^32
+        assert_eq!(decode_oid(&[0x2b, 0x90, 0x80, 0x80, 0x80, 0x00]), None);
+    }
+
+    #[test]
+    fn counter64_round_trip() {
+        let values = [
+            (vec![0x00], 0),
+            (vec![0x7f], 127),
+            (vec![0x00, 0x80], 128),
+            (vec![0xff, 0xff, 0xff, 0xff], u32::MAX as u64),
+            (vec![0x01, 0x00, 0x00, 0x00, 0x00], 1 << 32),
+            (vec![0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], u64::MAX)];
+        for (content, value) in values {
+            let encoded = encode(COUNTER64, &content);
+            let mut reader = Reader::new(&encoded);
+            assert_eq!(reader.read_tag(COUNTER64).and_then(decode_unsigned), Some(value), "{}", value);
+        }
+        assert_eq!(decode_unsigned(&[]), None);
+        assert_eq!(decode_unsigned(&[0x01, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn long_length_round_trip() {
+        for size in [0, 127, 128, 255, 256, 70000] {
+            let content = vec![0xaa; size];
+            let encoded = encode(OCTET_STRING, &content);
+            let mut reader = Reader::new(&encoded);
+            assert_eq!(reader.read_tag(OCTET_STRING), Some(&content[..]), "{}", size);
+            assert!(reader.is_empty());
+        }
+        // Content shorter than its length
+        assert_eq!(Reader::new(&[OCTET_STRING, 3, 1, 2]).read(), None);
+    }
+}
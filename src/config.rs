@@ -5,14 +5,21 @@ use std::fs;
 use std::process;
 use rand::random;
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::sync::mpsc::Sender;
+use std::time::Duration;
 use crate::checker::CheckResult;
+use pnet::ipnetwork::IpNetwork;
 
+// Largest target range is /16 for IPv4, /112 for IPv6: 65536 addresses
+const MAX_RANGE_HOST_BITS: u8 = 16;
+
+#[derive(Clone)]
 pub struct ProbeConfig {
     pub name: String,
     pub host: String,
     pub check_type: String,
-    pub interval: i64,
+    pub interval: Duration,
     pub config: HashMap<String, yaml::Yaml>,
     pub labels: HashMap<String, String>
 }
@@ -62,19 +69,38 @@ pub fn load_config() -> (Vec<ProbeConfig>, Vec<ProcessConfig>, Vec<OutputConfig>
         },
     };
     let mut probes = Vec::<ProbeConfig>::new();
+    // Config options applied to every probe unless overridden in the probe config
+    let mut defaults = HashMap::<String, Yaml>::new();
+    match cfg[0]["defaults"] {
+        yaml_rust::Yaml::Hash(ref d) => {
+            for (d_name, d_value) in d {
+                defaults.insert(d_name.clone().into_string().unwrap(), d_value.clone());
+            }
+        },
+        yaml_rust::Yaml::BadValue => {},
+        _ => {
+            error!("Defaults should be a HashMap");
+            process::exit(1);
+        }
+    }
     debug!("Loading probes");
     match cfg[0]["hosts"] {
         yaml_rust::Yaml::Hash(ref h) => {
             for (key, value) in h {
                 let mut host = ProbeConfig{
                     name: key.clone().into_string().unwrap(),
-                    host: value["addr"].clone().into_string().unwrap(),
+                    host: String::new(),
                     check_type: value["check"].clone().into_string().unwrap(),
-                    interval: value["interval"].clone().into_i64().unwrap(),
-                    config: HashMap::new(),
+                    interval: match parse_duration(&value["interval"]) {
+                        Some(i) => i,
+                        None => {
+                            error!("Failed to parse interval of {:?}", key);
+                            process::exit(1);
+                        }
+                    },
+                    config: defaults.clone(),
                     labels: HashMap::new()
                 };
-                debug!("Probe name: {}, host: {}", host.name, host.host);
                 match value["labels"] {
                     yaml_rust::Yaml::Hash(ref l) => {
                         for (l_key, l_value) in l {
@@ -97,7 +123,26 @@ pub fn load_config() -> (Vec<ProbeConfig>, Vec<ProcessConfig>, Vec<OutputConfig>
                         process::exit(1);
                     }
                 }
-                probes.push(host);
+                if let Some(netns) = host.config.get("netns") {
+                    host.labels.insert(String::from("netns"), netns.clone().into_string().unwrap());
+                }
+                let single = matches!(value["addr"], yaml_rust::Yaml::String(ref a) if !is_range(a))
+                    && value["targets_file"].is_badvalue();
+                if single {
+                    host.host = value["addr"].clone().into_string().unwrap();
+                    debug!("Probe name: {}, host: {}", host.name, host.host);
+                    probes.push(host);
+                } else {
+                    for (target, labels) in load_targets(value) {
+                        let mut target_host = host.clone();
+                        target_host.name = format!("{}_{}", host.name, target);
+                        target_host.host = target.clone();
+                        target_host.labels.insert(String::from("target"), target);
+                        target_host.labels.extend(labels);
+                        debug!("Probe name: {}, host: {}", target_host.name, target_host.host);
+                        probes.push(target_host);
+                    }
+                }
             };
             if *app.get_one::<bool>("remote").unwrap() {
                 probes.push(
@@ -105,7 +150,7 @@ pub fn load_config() -> (Vec<ProbeConfig>, Vec<ProcessConfig>, Vec<OutputConfig>
                         name: format!("remote_listener"),
                         host: format!("remote_listener"),
                         check_type: format!("remote_listener"),
-                        interval: 0,
+                        interval: Duration::ZERO,
                         config: HashMap::new(),
                         labels: HashMap::new()
                     });
@@ -247,3 +292,280 @@ pub fn load_config() -> (Vec<ProbeConfig>, Vec<ProcessConfig>, Vec<OutputConfig>
     }
     return (probes, processes, outputs);
 }
+
+// Parse duration: integer or float number of seconds, or number with unit, e.g. 250ms, 1.5s, 5m
+pub fn parse_duration(value: &Yaml) -> Option<Duration> {
+    match value {
+        yaml_rust::Yaml::Integer(i) if *i >= 0 => Some(Duration::from_secs(*i as u64)),
+        yaml_rust::Yaml::Real(_) => value.as_f64()
+            .and_then(|f| Duration::try_from_secs_f64(f).ok()),
+        yaml_rust::Yaml::String(s) => {
+            let s = s.trim();
+            let unit_start = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+            let (number, unit) = s.split_at(unit_start);
+            let number = number.parse::<f64>().ok()?;
+            let multiplier = match unit.trim() {
+                "ns" => 0.000_000_001,
+                "us" | "µs" => 0.000_001,
+                "ms" => 0.001,
+                "" | "s" => 1.0,
+                "m" => 60.0,
+                "h" => 3600.0,
+                "d" => 86400.0,
+                _ => return None
+            };
+            Duration::try_from_secs_f64(number * multiplier).ok()
+        },
+        _ => None
+    }
+}
+
+// Optional duration from module config
+pub fn get_duration(config: &HashMap<String, Yaml>, name: &str, default: Duration) -> Duration {
+    match config.get(name) {
+        Some(value) => match parse_duration(value) {
+            Some(d) => d,
+            None => {
+                error!("Failed to parse {}: {:?}", name, value);
+                process::exit(1);
+            }
+        },
+        None => default
+    }
+}
+
+// Required duration from module config
+pub fn get_required_duration(config: &HashMap<String, Yaml>, name: &str) -> Duration {
+    if !config.contains_key(name) {
+        error!("Missing {}", name);
+        process::exit(1);
+    }
+    get_duration(config, name, Duration::ZERO)
+}
+
+// Optional string or list of strings from module config
+pub fn get_strings(config: &HashMap<String, Yaml>, name: &str) -> Vec<String> {
+    match config.get(name) {
+        Some(yaml_rust::Yaml::Array(a)) => a.iter().map(|s| s.clone().into_string().unwrap()).collect(),
+        Some(value) => vec![value.clone().into_string().unwrap()],
+        None => Vec::new()
+    }
+}
+
+// Address with a prefix length, other addresses with '/' such as URLs are single targets
+fn is_range(addr: &str) -> bool {
+    addr.split_once('/').map(|(ip, _)| ip.parse::<IpAddr>().is_ok()).unwrap_or(false)
+}
+
+// Expand CIDR range with optional port, e.g. 10.0.0.0/30 or 10.0.0.0/30:80
+fn expand_range(range: &str) -> Result<Vec<String>, String> {
+    let (network, port) = match range.rsplit_once(':') {
+        Some((n, p)) if n.contains('/') => (n, Some(p)),
+        _ => (range, None)
+    };
+    let network = network.parse::<IpNetwork>()
+        .map_err(|e| format!("Failed to parse target range {}: {}", range, e))?;
+    let host_bits = match network {
+        IpNetwork::V4(n) => 32 - n.prefix(),
+        IpNetwork::V6(n) => 128 - n.prefix()
+    };
+    if host_bits > MAX_RANGE_HOST_BITS {
+        return Err(format!("Target range {} is larger than {} addresses", range, 1 << MAX_RANGE_HOST_BITS));
+    }
+    let mut result = Vec::new();
+    for ip in network.iter() {
+        if let IpNetwork::V4(n) = network {
+            if n.prefix() < 31 && (ip == n.network() || ip == n.broadcast()) {
+                continue;
+            }
+        }
+        match port {
+            Some(p) if ip.is_ipv6() => result.push(format!("[{}]:{}", ip, p)),
+            Some(p) => result.push(format!("{}:{}", ip, p)),
+            None => result.push(ip.to_string())
+        }
+    }
+    Ok(result)
+}
+
+fn range_targets(range: &str) -> Vec<(String, HashMap<String, String>)> {
+    match expand_range(range) {
+        Ok(targets) => targets.into_iter().map(|t| (t, HashMap::new())).collect(),
+        Err(e) => {
+            error!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+// Load targets from CSV file. First column is the target, other columns are labels named after the header
+fn load_targets_file(path: &str) -> Vec<(String, HashMap<String, String>)> {
+    let text = match fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(err) => {
+            error!("Failed to load targets file {}: {}", path, err);
+            process::exit(1);
+        }
+    };
+    let mut lines = text.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'));
+    let header: Vec<String> = match lines.next() {
+        Some(h) => h.split(',').map(|c| String::from(c.trim())).collect(),
+        None => return Vec::new()
+    };
+    let mut result = Vec::new();
+    for line in lines {
+        let mut columns = line.split(',').map(|c| String::from(c.trim()));
+        let target = columns.next().unwrap();
+        let mut labels = HashMap::new();
+        for (name, column) in header.iter().skip(1).zip(columns) {
+            if !column.is_empty() {
+                labels.insert(name.clone(), column);
+            }
+        }
+        result.push((target, labels));
+    }
+    result
+}
+
+fn load_targets(value: &Yaml) -> Vec<(String, HashMap<String, String>)> {
+    let mut targets = Vec::new();
+    match value["addr"] {
+        yaml_rust::Yaml::String(ref s) if is_range(s) => {
+            targets.extend(range_targets(s));
+        },
+        yaml_rust::Yaml::String(ref s) => {
+            targets.push((s.clone(), HashMap::new()));
+        },
+        yaml_rust::Yaml::Array(ref a) => {
+            for t in a {
+                let t = t.clone().into_string().unwrap();
+                if is_range(&t) {
+                    targets.extend(range_targets(&t));
+                } else {
+                    targets.push((t, HashMap::new()));
+                }
+            }
+        },
+        _ => {}
+    }
+    if let Some(path) = value["targets_file"].as_str() {
+        targets.extend(load_targets_file(path));
+    }
+    if targets.is_empty() {
+        error!("Probe should have addr or targets_file");
+        process::exit(1);
+    }
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn duration(yaml: &str) -> Option<Duration> {
+        parse_duration(&YamlLoader::load_from_str(yaml).unwrap()[0])
+    }
+
+    #[test]
+    fn duration_units() {
+        assert_eq!(duration("5"), Some(Duration::from_secs(5)));
+        assert_eq!(duration("0"), Some(Duration::ZERO));
+        assert_eq!(duration("10s"), Some(Duration::from_secs(10)));
+        assert_eq!(duration("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(duration("20us"), Some(Duration::from_micros(20)));
+        assert_eq!(duration("100ns"), Some(Duration::from_nanos(100)));
+        assert_eq!(duration("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(duration("1d"), Some(Duration::from_secs(86400)));
+        assert_eq!(duration("\" 30 s \""), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn duration_fractions() {
+        assert_eq!(duration("0.25"), Some(Duration::from_millis(250)));
+        assert_eq!(duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(duration("0.5m"), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn duration_invalid() {
+        assert_eq!(duration("-1"), None);
+        assert_eq!(duration("-0.5"), None);
+        assert_eq!(duration("-5s"), None);
+        assert_eq!(duration(".inf"), None);
+        assert_eq!(duration(".nan"), None);
+        assert_eq!(duration("99999999999999999999999d"), None);
+        assert_eq!(duration("1e300"), None);
+        assert_eq!(duration("soon"), None);
+        assert_eq!(duration("5 parsecs"), None);
+        assert_eq!(duration("s"), None);
+        assert_eq!(duration("1.2.3s"), None);
+        assert_eq!(duration("[1]"), None);
+        assert_eq!(duration("true"), None);
+    }
+
+    #[test]
+    fn range_detection() {
+        assert!(is_range("10.0.0.0/24"));
+        assert!(is_range("10.0.0.0/24:80"));
+        assert!(is_range("2001:db8::/120"));
+        assert!(is_range("10.0.0.0/33"));
+        assert!(!is_range("10.0.0.1"));
+        assert!(!is_range("http://host:50051"));
+        assert!(!is_range("https://10.0.0.1:443/path"));
+        assert!(!is_range("host.example/24"));
+    }
+
+    #[test]
+    fn expand_ranges() {
+        assert_eq!(expand_range("10.0.0.0/30").unwrap(), ["10.0.0.1", "10.0.0.2"]);
+        assert_eq!(expand_range("10.0.0.0/30:8080").unwrap(), ["10.0.0.1:8080", "10.0.0.2:8080"]);
+        assert_eq!(expand_range("10.0.0.5/32").unwrap(), ["10.0.0.5"]);
+        assert_eq!(expand_range("10.0.0.4/31").unwrap(), ["10.0.0.4", "10.0.0.5"]);
+        assert_eq!(expand_range("2001:db8::/127").unwrap(), ["2001:db8::", "2001:db8::1"]);
+        assert_eq!(expand_range("2001:db8::/127:53").unwrap(), ["[2001:db8::]:53", "[2001:db8::1]:53"]);
+    }
+
+    #[test]
+    fn range_size_limit() {
+        assert_eq!(expand_range("10.0.0.0/16").unwrap().len(), 65534);
+        assert_eq!(expand_range("2001:db8::/112").unwrap().len(), 65536);
+        assert!(expand_range("10.0.0.0/15").is_err());
+        assert!(expand_range("2001:db8::/111").is_err());
+        assert!(expand_range("0.0.0.0/0").is_err());
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        assert!(expand_range("10.0.0.0/33").is_err());
+        assert!(expand_range("10.0.0.0/").is_err());
+        assert!(expand_range("10.0.0.0/x").is_err());
+        assert!(expand_range("10.0.0.256/24").is_err());
+    }
+
+    #[test]
+    fn targets_file() {
+        let path = std::env::temp_dir().join(format!("targets-{}.csv", std::process::id()));
+        fs::write(&path, "# comment\naddr, site ,rack\n\n10.0.0.1,ams,r1\n10.0.0.2, fra\n  10.0.0.3 ,,r3\n# 10.0.0.4,x,y\n").unwrap();
+        let targets = load_targets_file(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        let labels = |pairs: &[(&str, &str)]| pairs.iter()
+            .map(|(l, v)| (String::from(*l), String::from(*v)))
+            .collect::<HashMap<String, String>>();
+        assert_eq!(targets, [
+            (String::from("10.0.0.1"), labels(&[("site", "ams"), ("rack", "r1")])),
+            (String::from("10.0.0.2"), labels(&[("site", "fra")])),
+            (String::from("10.0.0.3"), labels(&[("rack", "r3")]))]);
+    }
+
+    #[test]
+    fn empty_targets_file() {
+        let path = std::env::temp_dir().join(format!("targets-empty-{}.csv", std::process::id()));
+        fs::write(&path, "# only a comment\n").unwrap();
+        let targets = load_targets_file(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        assert!(targets.is_empty());
+    }
+}
@@ -0,0 +1,330 @@
+extern crate pnet;
+
+use log::{debug, error};
+use crate::config::{ProbeConfig, get_duration};
+use crate::scheduler::Scheduler;
+use yaml_rust::Yaml;
+use rand::random;
+use std::net::Ipv4Addr;
+use std::process;
+use std::time::{Duration, Instant};
+use std::sync::mpsc::Sender;
+use pnet::datalink::{self, Channel, DataLinkReceiver, DataLinkSender, MacAddr};
+use pnet::packet::Packet;
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ipv4::{self, Ipv4Packet, MutableIpv4Packet};
+use pnet::packet::udp::{self, MutableUdpPacket, UdpPacket};
+use std::collections::HashMap;
+use crate::checker::CheckResult;
+
+const CLIENT_PORT: u16 = 68;
+const SERVER_PORT: u16 = 67;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+// Fixed BOOTP header followed by the magic cookie
+const HEADER_SIZE: usize = 240;
+// Some servers drop messages shorter than a BOOTP message
+const MINIMUM_SIZE: usize = 300;
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+const RELEASE: u8 = 7;
+
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETERS: u8 = 55;
+const OPTION_END: u8 = 255;
+
+pub struct DhcpChecker {
+    interface: String,
+    scheduler: Scheduler,
+    timeout: Duration,
+    mac: Option<MacAddr>,
+    request: bool,
+    name: String,
+    precision: i64,
+    labels: HashMap<String, String>
+}
+
+struct Reply {
+    message_type: u8,
+    server: Ipv4Addr,
+    server_mac: MacAddr,
+    offered: Ipv4Addr
+}
+
+impl DhcpChecker {
+    pub fn new(config: &ProbeConfig) -> Self {
+        Self{
+            name: config.name.clone(),
+            interface: match config.config.get("interface").and_then(|i| i.as_str()) {
+                Some(interface) => String::from(interface),
+                None => {
+                    error!("Interface is required for {}", config.name);
+                    process::exit(1);
+                }
+            },
+            scheduler: Scheduler::new(config),
+            timeout: get_duration(&config.config, "timeout", Duration::from_secs(3)),
+            mac: config.config.get("mac").map(|m| {
+                let mac = m.as_str().unwrap_or_default();
+                match mac.parse::<MacAddr>() {
+                    Ok(mac) => mac,
+                    Err(e) => {
+                        error!("Invalid mac {} of {}: {}", mac, config.name, e);
+                        process::exit(1);
+                    }
+                }
+            }),
+            request: config.config.get("request")
+                .unwrap_or(&Yaml::Boolean(false))
+                .clone()
+                .into_bool()
+                .unwrap(),
+            precision: config.config.get("precision")
+                .unwrap_or(&Yaml::Integer(1))
+                .clone()
+                .into_i64()
+                .unwrap(),
+            labels: config.labels.clone()
+        }
+    }
+
+    fn emit(&self, sender: &Sender<CheckResult>, labels: HashMap<String, String>, value_name: &str, value: f32) {
+        let mut to_emit = CheckResult{
+            name: self.name.clone(),
+            values: HashMap::new(),
+            processes: Vec::new(),
+            labels};
+        to_emit.values.insert(String::from(value_name), value);
+        sender.send(to_emit).unwrap();
+    }
+
+    fn to_time(&self, time: Duration) -> f32 {
+        (time.as_micros() as f32) / self.precision as f32
+    }
+}
+
+// BOOTP request with the broadcast flag so replies reach a client without an address
+fn dhcp_message(mac: MacAddr, xid: u32, message_type: u8, ciaddr: Ipv4Addr, options: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let mut message = vec![0; HEADER_SIZE];
+    message[0] = 1;
+    message[1] = 1;
+    message[2] = 6;
+    message[4..8].copy_from_slice(&xid.to_be_bytes());
+    if message_type != RELEASE {
+        message[10] = 0x80;
+    }
+    message[12..16].copy_from_slice(&ciaddr.octets());
+    message[28..34].copy_from_slice(&mac.octets());
+    message[236..240].copy_from_slice(&MAGIC_COOKIE);
+    message.extend([OPTION_MESSAGE_TYPE, 1, message_type]);
+    for (code, value) in options {
+        message.push(*code);
+        message.push(value.len() as u8);
+        message.extend(value);
+    }
+    message.push(OPTION_END);
+    if message.len() < MINIMUM_SIZE {
+        message.resize(MINIMUM_SIZE, 0);
+    }
+    message
+}
+
+fn udp_frame(source_mac: MacAddr, destination_mac: MacAddr, source: Ipv4Addr, destination: Ipv4Addr, payload: &[u8]) -> Vec<u8> {
+    let udp_size = UdpPacket::minimum_packet_size() + payload.len();
+    let ip_size = Ipv4Packet::minimum_packet_size() + udp_size;
+    let mut udp_buffer = vec![0; udp_size];
+    let mut udp = MutableUdpPacket::new(&mut udp_buffer[..]).unwrap();
+    udp.set_source(CLIENT_PORT);
+    udp.set_destination(SERVER_PORT);
+    udp.set_length(udp_size as u16);
+    udp.set_payload(payload);
+    udp.set_checksum(udp::ipv4_checksum(&udp.to_immutable(), &source, &destination));
+    let mut ip_buffer = vec![0; ip_size];
+    let mut ip = MutableIpv4Packet::new(&mut ip_buffer[..]).unwrap();
+    ip.set_version(4);
+    ip.set_header_length(5);
+    ip.set_total_length(ip_size as u16);
+    ip.set_ttl(64);
+    ip.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+    ip.set_source(source);
+    ip.set_destination(destination);
+    ip.set_payload(&udp_buffer);
+    ip.set_checksum(ipv4::checksum(&ip.to_immutable()));
+    let mut ethernet_buffer = vec![0; EthernetPacket::minimum_packet_size() + ip_size];
+    let mut ethernet = MutableEthernetPacket::new(&mut ethernet_buffer[..]).unwrap();
+    ethernet.set_destination(destination_mac);
+    ethernet.set_source(source_mac);
+    ethernet.set_ethertype(EtherTypes::Ipv4);
+    ethernet.set_payload(&ip_buffer);
+    ethernet_buffer
+}
+
+// Server reply to our transaction, server is identified by option 54 or by the sender address
+fn parse_reply(frame: &[u8], mac: MacAddr, xid: u32) -> Option<Reply> {
+    let ethernet = EthernetPacket::new(frame)?;
+    if ethernet.get_ethertype() != EtherTypes::Ipv4 {
+        return None;
+    }
+    let ip = Ipv4Packet::new(ethernet.payload())?;
+    if ip.get_next_level_protocol() != IpNextHeaderProtocols::Udp {
+        return None;
+    }
+    let udp = UdpPacket::new(ip.payload())?;
+    if udp.get_source() != SERVER_PORT || udp.get_destination() != CLIENT_PORT {
+        return None;
+    }
+    let message = udp.payload();
+    if message.len() < HEADER_SIZE || message[0] != 2 || message[4..8] != xid.to_be_bytes()
+        || message[28..34] != mac.octets() || message[236..240] != MAGIC_COOKIE {
+        return None;
+    }
+    let mut message_type = None;
+    let mut server = ip.get_source();
+    let mut options = &message[HEADER_SIZE..];
+    while let Some(&code) = options.first() {
+        match code {
+            0 => {
+                options = &options[1..];
+                continue;
+            },
+            OPTION_END => break,
+            _ => {}
+        }
+        let length = *options.get(1)? as usize;
+        let value = options.get(2..2 + length)?;
+        match code {
+            OPTION_MESSAGE_TYPE if length == 1 => message_type = Some(value[0]),
+            OPTION_SERVER_ID if length == 4 => server = Ipv4Addr::new(value[0], value[1], value[2], value[3]),
+            _ => {}
+        }
+        options = &options[2 + length..];
+    }
+    Some(Reply{
+        message_type: message_type?,
+        server,
+        server_mac: ethernet.get_source(),
+        offered: Ipv4Addr::new(message[16], message[17], message[18], message[19])
+    })
+}
+
+// First reply of expected types, with the time it was received
+fn wait_reply(rx: &mut Box<dyn DataLinkReceiver>, mac: MacAddr, xid: u32, types: &[u8], deadline: Instant) -> Option<(Instant, Reply)> {
+    while Instant::now() < deadline {
+        match rx.next() {
+            Ok(frame) => {
+                let now = Instant::now();
+                if let Some(reply) = parse_reply(frame, mac, xid) {
+                    if types.contains(&reply.message_type) {
+                        return Some((now, reply));
+                    }
+                }
+            },
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::TimedOut {
+                    debug!("Error getting packet {:?}", e);
+                }
+            }
+        }
+    }
+    None
+}
+
+fn send(tx: &mut Box<dyn DataLinkSender>, frame: &[u8]) -> bool {
+    match tx.send_to(frame, None) {
+        Some(Ok(_)) => true,
+        _ => {
+            debug!("Failed to send dhcp message");
+            false
+        }
+    }
+}
+
+pub fn dhcp(mut checker: DhcpChecker, sender: Sender<CheckResult>) {
+    let interface = match datalink::interfaces().into_iter().find(|i| i.name == checker.interface) {
+        Some(i) => i,
+        None => {
+            error!("Interface {} not found", checker.interface);
+            return;
+        }
+    };
+    let interface_mac = interface.mac.unwrap_or(MacAddr::zero());
+    let mac = checker.mac.unwrap_or(interface_mac);
+    let config = datalink::Config {
+        read_timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let (mut tx, mut rx) = match datalink::channel(&interface, config) {
+        Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
+        Ok(_) => {
+            error!("Unsupported channel type on {}", checker.interface);
+            return;
+        },
+        Err(e) => {
+            error!("Failed to open {}: {}", checker.interface, e);
+            return;
+        }
+    };
+    let parameters = vec![1, 3, 6, 51, 54];
+    loop {
+        checker.scheduler.wait();
+        let xid = random::<u32>();
+        let discover = dhcp_message(mac, xid, DISCOVER, Ipv4Addr::UNSPECIFIED, &[(OPTION_PARAMETERS, parameters.clone())]);
+        let frame = udp_frame(interface_mac, MacAddr::broadcast(), Ipv4Addr::UNSPECIFIED, Ipv4Addr::BROADCAST, &discover);
+        let start = Instant::now();
+        let offer = if send(&mut tx, &frame) {
+            wait_reply(&mut rx, mac, xid, &[OFFER], start + checker.timeout)
+        } else {
+            None
+        };
+        let (received, offer) = match offer {
+            Some(o) => o,
+            None => {
+                checker.emit(&sender, checker.labels.clone(), "loss", 1.0);
+                continue;
+            }
+        };
+        // Loss keeps probe labels only, there is no server to name when nothing is offered
+        let mut labels = checker.labels.clone();
+        labels.insert(String::from("server"), offer.server.to_string());
+        labels.insert(String::from("offered_ip"), offer.offered.to_string());
+        checker.emit(&sender, labels.clone(), "rtt", checker.to_time(received.duration_since(start)));
+        if !checker.request {
+            checker.emit(&sender, checker.labels.clone(), "loss", 0.0);
+            continue;
+        }
+        let request = dhcp_message(mac, xid, REQUEST, Ipv4Addr::UNSPECIFIED, &[
+            (OPTION_REQUESTED_IP, offer.offered.octets().to_vec()),
+            (OPTION_SERVER_ID, offer.server.octets().to_vec()),
+            (OPTION_PARAMETERS, parameters.clone())]);
+        let frame = udp_frame(interface_mac, MacAddr::broadcast(), Ipv4Addr::UNSPECIFIED, Ipv4Addr::BROADCAST, &request);
+        let start = Instant::now();
+        let ack = if send(&mut tx, &frame) {
+            wait_reply(&mut rx, mac, xid, &[ACK, NAK], start + checker.timeout)
+        } else {
+            None
+        };
+        match ack {
+            Some((received, ack)) if ack.message_type == ACK => {
+                checker.emit(&sender, labels, "ack_rtt", checker.to_time(received.duration_since(start)));
+                checker.emit(&sender, checker.labels.clone(), "loss", 0.0);
+                // Give the lease back so probes do not exhaust the pool
+                let release = dhcp_message(mac, random::<u32>(), RELEASE, ack.offered, &[
+                    (OPTION_SERVER_ID, ack.server.octets().to_vec())]);
+                send(&mut tx, &udp_frame(interface_mac, ack.server_mac, ack.offered, ack.server, &release));
+            },
+            Some(_) => {
+                debug!("Request for {} declined by {}", offer.offered, offer.server);
+                checker.emit(&sender, checker.labels.clone(), "loss", 1.0);
+            },
+            None => {
+                checker.emit(&sender, checker.labels.clone(), "loss", 1.0);
+            }
+        }
+    }
+}
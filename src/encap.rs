@@ -0,0 +1,159 @@
+extern crate pnet;
+
+use crate::interface::{route_source, source_ipv4};
+use yaml_rust::Yaml;
+use log::error;
+use std::process;
+use rand::random;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use pnet::datalink::MacAddr;
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ipv4::{self, Ipv4Packet, MutableIpv4Packet};
+use pnet::packet::udp::{MutableUdpPacket, UdpPacket};
+
+// Encapsulation of raw probes for overlay testing: GRE (RFC 2784/2890) or VXLAN (RFC 7348)
+const GRE_KEY_PRESENT: u16 = 0x2000;
+const VXLAN_VNI_PRESENT: u8 = 0x08;
+const VXLAN_PORT: u16 = 4789;
+const VXLAN_HEADER_SIZE: usize = 8;
+// Locally administered source of inner VXLAN frames
+const INNER_SOURCE_MAC: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 0x01);
+
+#[derive(Clone)]
+enum Kind {
+    Gre{key: Option<u32>},
+    Vxlan{vni: u32, port: u16, mac: MacAddr}
+}
+
+pub struct Encap {
+    kind: Kind,
+    remote: String,
+    local: Option<String>
+}
+
+// Encapsulation with resolved outer addresses
+pub struct Tunnel {
+    kind: Kind,
+    local: Ipv4Addr,
+    remote: Ipv4Addr
+}
+
+impl Encap {
+    // encap section of module config, None if the probe is not encapsulated
+    pub fn new(config: &HashMap<String, Yaml>) -> Option<Self> {
+        let encap = config.get("encap")?;
+        let kind = match encap["type"].as_str() {
+            Some("gre") => Kind::Gre{key: encap["key"].as_i64().map(|k| k as u32)},
+            Some("vxlan") => Kind::Vxlan{
+                vni: match encap["vni"].as_i64() {
+                    Some(vni) => vni as u32,
+                    None => {
+                        error!("VXLAN encapsulation requires vni");
+                        process::exit(1);
+                    }
+                },
+                port: encap["port"].as_i64().unwrap_or(VXLAN_PORT as i64) as u16,
+                mac: match encap["inner_mac"].as_str().map(|m| m.parse::<MacAddr>()) {
+                    Some(Ok(mac)) => mac,
+                    Some(Err(e)) => {
+                        error!("Invalid inner_mac: {}", e);
+                        process::exit(1);
+                    },
+                    None => MacAddr::broadcast()
+                }
+            },
+            Some(other) => {
+                error!("Unknown encapsulation {}", other);
+                process::exit(1);
+            },
+            None => {
+                error!("Encapsulation requires type");
+                process::exit(1);
+            }
+        };
+        let remote = match encap["remote"].as_str() {
+            Some(remote) => String::from(remote),
+            None => {
+                error!("Encapsulation requires remote");
+                process::exit(1);
+            }
+        };
+        Some(Self{
+            kind,
+            remote,
+            local: encap["local"].as_str().map(String::from)
+        })
+    }
+
+    // Outer addresses, local one is picked from the routing table if not set
+    pub fn tunnel(&self, bind_interface: &Option<String>) -> Result<Tunnel, String> {
+        let remote = source_ipv4(&self.remote).ok_or(format!("invalid tunnel remote {}", self.remote))?;
+        let local = match &self.local {
+            Some(local) => source_ipv4(local).ok_or(format!("invalid tunnel local {}", local))?,
+            None => route_source(remote, bind_interface).map_err(|e| format!("no route to {}: {}", remote, e))?
+        };
+        Ok(Tunnel{kind: self.kind.clone(), local, remote})
+    }
+}
+
+impl Tunnel {
+    pub fn remote(&self) -> Ipv4Addr {
+        self.remote
+    }
+
+    // Outer IPv4 packet carrying inner IPv4 packet. Inner packet stays at the end,
+    // so sent timestamps can still be matched by the inner packet
+    pub fn wrap(&self, inner: &[u8]) -> Vec<u8> {
+        let (protocol, payload) = match self.kind {
+            Kind::Gre{key} => {
+                let flags = if key.is_some() { GRE_KEY_PRESENT } else { 0 };
+                let mut gre = Vec::with_capacity(8 + inner.len());
+                gre.extend(flags.to_be_bytes());
+                gre.extend(EtherTypes::Ipv4.0.to_be_bytes());
+                if let Some(key) = key {
+                    gre.extend(key.to_be_bytes());
+                }
+                gre.extend_from_slice(inner);
+                (IpNextHeaderProtocols::Gre, gre)
+            },
+            Kind::Vxlan{vni, port, mac} => {
+                let mut frame = vec![0; EthernetPacket::minimum_packet_size() + inner.len()];
+                let mut ethernet = MutableEthernetPacket::new(&mut frame[..]).unwrap();
+                ethernet.set_destination(mac);
+                ethernet.set_source(INNER_SOURCE_MAC);
+                ethernet.set_ethertype(EtherTypes::Ipv4);
+                ethernet.set_payload(inner);
+                let mut vxlan = vec![0; VXLAN_HEADER_SIZE];
+                vxlan[0] = VXLAN_VNI_PRESENT;
+                vxlan[4..8].copy_from_slice(&(vni << 8).to_be_bytes());
+                vxlan.extend(frame);
+                let udp_size = UdpPacket::minimum_packet_size() + vxlan.len();
+                let mut datagram = vec![0; udp_size];
+                let mut udp = MutableUdpPacket::new(&mut datagram[..]).unwrap();
+                // Source port spreads probes over ECMP paths, checksum is optional over IPv4
+                udp.set_source(49152 + random::<u16>() % 16384);
+                udp.set_destination(port);
+                udp.set_length(udp_size as u16);
+                udp.set_checksum(0);
+                udp.set_payload(&vxlan);
+                (IpNextHeaderProtocols::Udp, datagram)
+            }
+        };
+        let size = Ipv4Packet::minimum_packet_size() + payload.len();
+        let mut packet = vec![0; size];
+        let mut ip = MutableIpv4Packet::new(&mut packet[..]).unwrap();
+        ip.set_version(4);
+        ip.set_header_length(5);
+        ip.set_total_length(size as u16);
+        // No DF, the overhead comes on top of the probe size and the underlay may fragment it
+        ip.set_ttl(64);
+        ip.set_next_level_protocol(protocol);
+        ip.set_source(self.local);
+        ip.set_destination(self.remote);
+        ip.set_payload(&payload);
+        ip.set_checksum(ipv4::checksum(&ip.to_immutable()));
+        packet
+    }
+}
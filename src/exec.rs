@@ -0,0 +1,283 @@
+use crate::config::{ProbeConfig, get_duration};
+use crate::scheduler::Scheduler;
+use yaml_rust::Yaml;
+use std::sync::mpsc::Sender;
+use std::process::{self, Command, Stdio};
+use std::os::unix::process::CommandExt;
+use std::io::Read;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::checker::CheckResult;
+use std::collections::HashMap;
+use log::{debug, error};
+
+const STATUS_UNKNOWN: f32 = 3.0;
+
+pub struct ExecChecker {
+    command: Vec<String>,
+    scheduler: Scheduler,
+    timeout: Duration,
+    name: String,
+    uom_label: bool,
+    labels: HashMap<String, String>
+}
+
+impl ExecChecker {
+    pub fn new(config: &ProbeConfig) -> Self {
+        let mut result = Self{
+            name: config.name.clone(),
+            command: Vec::new(),
+            scheduler: Scheduler::new(config),
+            timeout: get_duration(&config.config, "timeout", config.interval),
+            uom_label: config.config.get("uom_label")
+                .unwrap_or(&Yaml::Boolean(false))
+                .clone()
+                .as_bool()
+                .unwrap(),
+            labels: config.labels.clone()
+        };
+        match config.config.get("command") {
+            Some(yaml_rust::Yaml::String(ref s)) => {
+                result.command.push(String::from("/bin/sh"));
+                result.command.push(String::from("-c"));
+                result.command.push(s.clone());
+            },
+            Some(yaml_rust::Yaml::Array(ref s)) => {
+                for arg in s {
+                    match arg.as_str() {
+                        Some(arg) => result.command.push(String::from(arg)),
+                        None => {
+                            error!("Command arguments of {} should be strings", config.name);
+                            process::exit(1);
+                        }
+                    }
+                }
+            },
+            _ => {}
+        }
+        if result.command.is_empty() {
+            error!("No command configured for {}", config.name);
+            process::exit(1);
+        }
+        result
+    }
+
+    fn emit(&self, sender: &Sender<CheckResult>, labels: HashMap<String, String>, value_name: String, value: f32) {
+        let mut to_emit = CheckResult{
+            name: self.name.clone(),
+            values: HashMap::new(),
+            processes: Vec::new(),
+            labels};
+        to_emit.values.insert(value_name, value);
+        sender.send(to_emit).unwrap();
+    }
+
+    // Returns exit code and stdout, None if the command failed to start or timed out.
+    // Codes other than 0-3 and death by signal are UNKNOWN
+    fn run(&self) -> Option<(i32, String)> {
+        // Own process group, so a timeout kills programs started by the command too
+        let mut child = match Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .process_group(0)
+            .spawn() {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("Failed to run {:?}: {}", self.command, e);
+                    return None;
+                }
+            };
+        let mut stdout = child.stdout.take().unwrap();
+        let reader = thread::spawn(move || {
+            let mut output = String::new();
+            _ = stdout.read_to_string(&mut output);
+            output
+        });
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => {
+                    let output = reader.join().unwrap_or_default();
+                    let code = status.code().filter(|c| (0..=3).contains(c)).unwrap_or(STATUS_UNKNOWN as i32);
+                    return Some((code, output));
+                },
+                Ok(None) => {
+                    if Instant::now() >= deadline {
+                        debug!("Command {:?} timed out", self.command);
+                        unsafe {
+                            libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                        }
+                        _ = child.wait();
+                        _ = reader.join();
+                        return None;
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                },
+                Err(e) => {
+                    error!("Failed to wait for {:?}: {}", self.command, e);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+struct PerfData {
+    label: String,
+    value: f32,
+    uom: String
+}
+
+// Split perfdata on spaces, keeping single quoted labels together
+fn split_perfdata(perfdata: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in perfdata.chars() {
+        if c == '\'' {
+            quoted = !quoted;
+            current.push(c);
+        } else if c.is_whitespace() && !quoted {
+            if !current.is_empty() {
+                result.push(current.clone());
+                current.clear();
+            }
+        } else {
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        result.push(current);
+    }
+    result
+}
+
+// Parse 'label'=value[UOM];[warn];[crit];[min];[max]
+fn parse_perfdata(item: &str) -> Option<PerfData> {
+    let (label, data) = item.rsplit_once('=')?;
+    let label = match label.strip_prefix('\'') {
+        Some(quoted) => quoted.strip_suffix('\'')?.replace("''", "'"),
+        None => String::from(label)
+    };
+    if label.is_empty() {
+        return None;
+    }
+    let value = data.split(';').next()?;
+    let number_end = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+' || c == 'e' || c == 'E'))
+        .unwrap_or(value.len());
+    let (number, uom) = value.split_at(number_end);
+    Some(PerfData{
+        label: label.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+            .collect(),
+        value: number.parse::<f32>().ok()?,
+        uom: String::from(uom)
+    })
+}
+
+// Perfdata follows '|' of the first line, and the first '|' of long text, where it
+// continues over the rest of the output
+fn extract_perfdata(output: &str) -> Vec<PerfData> {
+    let mut result = Vec::new();
+    let mut long_perfdata = false;
+    for (n, line) in output.lines().enumerate() {
+        let perfdata = if long_perfdata {
+            Some(line)
+        } else if let Some((_, perfdata)) = line.split_once('|') {
+            long_perfdata = n > 0;
+            Some(perfdata)
+        } else {
+            None
+        };
+        if let Some(perfdata) = perfdata {
+            for item in split_perfdata(perfdata) {
+                match parse_perfdata(&item) {
+                    Some(p) => result.push(p),
+                    None => debug!("Failed to parse perfdata {}", item)
+                }
+            }
+        }
+    }
+    result
+}
+
+pub fn exec(mut checker: ExecChecker, sender: Sender<CheckResult>) {
+    loop {
+        checker.scheduler.wait();
+        match checker.run() {
+            Some((code, output)) => {
+                checker.emit(&sender, checker.labels.clone(), String::from("status"), code as f32);
+                for perfdata in extract_perfdata(&output) {
+                    let mut labels = checker.labels.clone();
+                    if checker.uom_label && !perfdata.uom.is_empty() {
+                        labels.insert(String::from("uom"), perfdata.uom);
+                    }
+                    checker.emit(&sender, labels, perfdata.label, perfdata.value);
+                }
+            },
+            None => {
+                checker.emit(&sender, checker.labels.clone(), String::from("status"), STATUS_UNKNOWN);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(output: &str) -> Vec<(String, f32, String)> {
+        extract_perfdata(output).into_iter().map(|p| (p.label, p.value, p.uom)).collect()
+    }
+
+    fn value(label: &str, value: f32, uom: &str) -> (String, f32, String) {
+        (String::from(label), value, String::from(uom))
+    }
+
+    #[test]
+    fn quoted_labels() {
+        assert_eq!(split_perfdata("'disk /var'=10 load=1 'it''s'=2"), ["'disk /var'=10", "load=1", "'it''s'=2"]);
+        assert_eq!(values("OK | 'disk /var'=10 'it''s'=2"), [value("disk__var", 10.0, ""), value("it_s", 2.0, "")]);
+        assert_eq!(values("OK | 'a=b'=3"), [value("a_b", 3.0, "")]);
+    }
+
+    #[test]
+    fn units_and_thresholds() {
+        assert_eq!(values("OK | time=0.25s;1;2;0;10 size=512KB;;; pct=99.5%;90;95 c=1e3c"), [
+            value("time", 0.25, "s"),
+            value("size", 512.0, "KB"),
+            value("pct", 99.5, "%"),
+            value("c", 1000.0, "c")]);
+        assert_eq!(values("OK | temp=-5.5;0:10;@-10:20"), [value("temp", -5.5, "")]);
+    }
+
+    #[test]
+    fn multi_line_output() {
+        let output = "DISK OK - free space | root=60%;80;90\nlong text line 1\nline 2 | /boot=30%;80;90\n/home=70%;80;90\n/var=10%\n";
+        assert_eq!(values(output), [
+            value("root", 60.0, "%"),
+            value("_boot", 30.0, "%"),
+            value("_home", 70.0, "%"),
+            value("_var", 10.0, "%")]);
+        // Long text without perfdata
+        assert_eq!(values("OK | a=1\nno perfdata here\n"), [value("a", 1.0, "")]);
+        assert!(values("OK\ndetails\n").is_empty());
+    }
+
+    #[test]
+    fn malformed_perfdata() {
+        assert!(parse_perfdata("novalue").is_none());
+        assert!(parse_perfdata("=5").is_none());
+        assert!(parse_perfdata("''=5").is_none());
+        assert!(parse_perfdata("a=").is_none());
+        assert!(parse_perfdata("a=U").is_none());
+        assert!(parse_perfdata("a=abc;1;2").is_none());
+        assert_eq!(values("OK | bad a=1 =2 b=x c=3"), [value("a", 1.0, ""), value("c", 3.0, "")]);
+        assert!(values("OK |").is_empty());
+        // Unterminated quote keeps the rest together
+        assert!(values("OK | 'open=1 b=2").is_empty());
+    }
+}
@@ -0,0 +1,377 @@
+use crate::config::ProbeConfig;
+use yaml_rust::Yaml;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::io::{BufRead, BufReader, Read};
+use std::process;
+use std::thread;
+use std::time::Duration;
+use crate::checker::CheckResult;
+use std::collections::HashMap;
+use regex::Regex;
+use log::{debug, error};
+
+// Senders keep connections open, idle ones are closed after the timeout
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const MAX_LINE: usize = 8192;
+const MAX_CONNECTIONS: usize = 64;
+
+// Metric path is mapped to labels by the first template with matching filter. Template segments
+// are label names, "value" for segments of the value name, "value*" for the rest of the path,
+// or empty to drop the segment
+struct Template {
+    filter: Option<Regex>,
+    segments: Vec<String>
+}
+
+pub struct GraphiteListenerChecker {
+    host: String,
+    protocol: String,
+    transport: String,
+    templates: Vec<Template>,
+    name: String,
+    labels: HashMap<String, String>,
+    gauges: Mutex<HashMap<String, f32>>
+}
+
+// Filter is a path prefix where "*" matches within one segment
+fn filter_regex(filter: &str) -> Regex {
+    let pattern = filter.split('.')
+        .map(|s| regex::escape(s).replace("\\*", "[^.]*"))
+        .collect::<Vec<String>>()
+        .join("\\.");
+    Regex::new(&format!("^{}(\\..*)?$", pattern)).unwrap()
+}
+
+impl GraphiteListenerChecker {
+    pub fn new(config: &ProbeConfig) -> Self {
+        let protocol = config.config.get("protocol")
+            .unwrap_or(&Yaml::String(String::from("graphite")))
+            .clone()
+            .into_string()
+            .unwrap();
+        let default_transport = if protocol == "statsd" { "udp" } else { "tcp" };
+        let mut result = Self{
+            host: config.host.clone(),
+            transport: config.config.get("transport")
+                .unwrap_or(&Yaml::String(String::from(default_transport)))
+                .clone()
+                .into_string()
+                .unwrap(),
+            protocol,
+            templates: Vec::new(),
+            name: config.name.clone(),
+            labels: config.labels.clone(),
+            gauges: Mutex::new(HashMap::new())
+        };
+        if let Some(yaml_rust::Yaml::Array(ref templates)) = config.config.get("templates") {
+            for template in templates {
+                let segments = match template["template"].as_str() {
+                    Some(t) => t.split('.').map(String::from).collect(),
+                    None => {
+                        error!("Template of {} requires template", config.name);
+                        process::exit(1);
+                    }
+                };
+                result.templates.push(Template{
+                    filter: template["filter"].clone().into_string().map(|f| filter_regex(&f)),
+                    segments
+                });
+            }
+        }
+        result
+    }
+
+    // Add labels from path segments, returns value name
+    fn apply_template(&self, path: &str, labels: &mut HashMap<String, String>) -> String {
+        let parts: Vec<&str> = path.split('.').collect();
+        let template = match self.templates.iter().find(|t| t.filter.as_ref().map(|f| f.is_match(path)).unwrap_or(true)) {
+            Some(t) => t,
+            None => return String::from(path)
+        };
+        let mut value = Vec::new();
+        let mut used = 0;
+        for (segment, part) in template.segments.iter().zip(parts.iter()) {
+            used += 1;
+            match segment.as_str() {
+                "" => {},
+                "value" => value.push(*part),
+                "value*" => {
+                    value.extend(&parts[used - 1..]);
+                    used = parts.len();
+                    break;
+                },
+                label => {
+                    labels.insert(String::from(label), String::from(*part));
+                }
+            }
+        }
+        // Segments beyond the template are kept in the value name
+        value.extend(&parts[used..]);
+        if value.is_empty() {
+            return String::from("value");
+        }
+        value.join(".")
+    }
+
+    fn emit(&self, sender: &Sender<CheckResult>, path: &str, value: f32, mut labels: HashMap<String, String>) {
+        let value_name = self.apply_template(path, &mut labels);
+        let mut to_emit = CheckResult{
+            name: self.name.clone(),
+            values: HashMap::new(),
+            processes: Vec::new(),
+            labels};
+        to_emit.values.insert(value_name, value);
+        sender.send(to_emit).unwrap();
+    }
+
+    // <path> <value> <timestamp>, timestamp is ignored
+    fn process_graphite(&self, sender: &Sender<CheckResult>, line: &str) -> Option<()> {
+        let mut fields = line.split_whitespace();
+        let path = fields.next()?;
+        let value = fields.next()?.parse::<f32>().ok()?;
+        self.emit(sender, path, value, self.labels.clone());
+        Some(())
+    }
+
+    // <bucket>:<value>|<type>[|@<sample rate>][|#<tag>:<value>,...]. Counters are scaled by
+    // sample rate, gauges with sign are changes of the previous value
+    fn process_statsd(&self, sender: &Sender<CheckResult>, line: &str) -> Option<()> {
+        let mut fields = line.split('|');
+        let (bucket, value) = fields.next()?.rsplit_once(':')?;
+        let metric_type = fields.next()?;
+        let mut rate = 1.0;
+        let mut labels = self.labels.clone();
+        for field in fields {
+            if let Some(r) = field.strip_prefix('@') {
+                rate = r.parse::<f32>().ok().filter(|r| *r > 0.0)?;
+            } else if let Some(tags) = field.strip_prefix('#') {
+                for tag in tags.split(',') {
+                    let (tag, tag_value) = tag.split_once(':').unwrap_or((tag, ""));
+                    labels.insert(String::from(tag), String::from(tag_value));
+                }
+            }
+        }
+        let number = value.parse::<f32>().ok()?;
+        let value = match metric_type {
+            "c" => number / rate,
+            "ms" | "h" | "d" => number,
+            "g" => {
+                let mut gauges = self.gauges.lock().unwrap();
+                let gauge = gauges.entry(String::from(bucket)).or_insert(0.0);
+                if value.starts_with('+') || value.starts_with('-') {
+                    *gauge += number;
+                } else {
+                    *gauge = number;
+                }
+                *gauge
+            },
+            _ => return None
+        };
+        self.emit(sender, bucket, value, labels);
+        Some(())
+    }
+
+    fn process_line(&self, sender: &Sender<CheckResult>, line: &str) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        let parsed = if self.protocol == "statsd" {
+            self.process_statsd(sender, line)
+        } else {
+            self.process_graphite(sender, line)
+        };
+        if parsed.is_none() {
+            debug!("Failed to parse {} line {}", self.protocol, line);
+        }
+    }
+
+    // Lines longer than MAX_LINE close the connection
+    fn read_connection(&self, stream: TcpStream, sender: &Sender<CheckResult>) {
+        if let Err(e) = stream.set_read_timeout(Some(IDLE_TIMEOUT)) {
+            debug!("Failed to set read timeout: {}", e);
+            return;
+        }
+        let mut reader = BufReader::new(stream);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match reader.by_ref().take(MAX_LINE as u64).read_until(b'\n', &mut line) {
+                Ok(0) => return,
+                Ok(size) if size >= MAX_LINE && !line.ends_with(b"\n") => {
+                    debug!("Line is longer than {} bytes, closing connection", MAX_LINE);
+                    return;
+                },
+                Ok(_) => self.process_line(sender, &String::from_utf8_lossy(&line)),
+                Err(e) => {
+                    debug!("Failed to read connection: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn listen_tcp(checker: Arc<GraphiteListenerChecker>, sender: Sender<CheckResult>) {
+    let listener = match TcpListener::bind(&checker.host) {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Failed to listen on {}: {}", checker.host, e);
+            return;
+        }
+    };
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if connections.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+                    debug!("Too many connections, dropping {:?}", stream.peer_addr());
+                    continue;
+                }
+                connections.fetch_add(1, Ordering::SeqCst);
+                let checker = Arc::clone(&checker);
+                let connections = Arc::clone(&connections);
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    checker.read_connection(stream, &sender);
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                });
+            },
+            Err(e) => debug!("Failed to accept connection: {}", e)
+        }
+    }
+}
+
+fn listen_udp(checker: Arc<GraphiteListenerChecker>, sender: Sender<CheckResult>) {
+    let socket = match UdpSocket::bind(&checker.host) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Failed to listen on {}: {}", checker.host, e);
+            return;
+        }
+    };
+    let mut buffer = [0u8; 65536];
+    loop {
+        match socket.recv(&mut buffer) {
+            Ok(size) => {
+                for line in String::from_utf8_lossy(&buffer[..size]).lines() {
+                    checker.process_line(&sender, line);
+                }
+            },
+            Err(e) => debug!("Failed to receive: {}", e)
+        }
+    }
+}
+
+pub fn graphite_listener(checker: GraphiteListenerChecker, sender: Sender<CheckResult>) {
+    let checker = Arc::new(checker);
+    if checker.transport == "udp" {
+        listen_udp(checker, sender);
+    } else {
+        listen_tcp(checker, sender);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, Receiver};
+
+    fn checker(templates: &[(Option<&str>, &str)]) -> GraphiteListenerChecker {
+        GraphiteListenerChecker{
+            host: String::new(),
+            protocol: String::from("statsd"),
+            transport: String::from("udp"),
+            templates: templates.iter().map(|(filter, template)| Template{
+                filter: filter.map(filter_regex),
+                segments: template.split('.').map(String::from).collect()
+            }).collect(),
+            name: String::from("statsd"),
+            labels: HashMap::from([(String::from("probe_name"), String::from("statsd"))]),
+            gauges: Mutex::new(HashMap::new())
+        }
+    }
+
+    fn statsd(checker: &GraphiteListenerChecker, line: &str) -> Option<(String, f32, HashMap<String, String>)> {
+        let (sender, receiver): (Sender<CheckResult>, Receiver<CheckResult>) = channel();
+        checker.process_statsd(&sender, line)?;
+        let result = receiver.try_recv().unwrap();
+        let (name, value) = result.values.into_iter().next().unwrap();
+        Some((name, value, result.labels))
+    }
+
+    fn template(checker: &GraphiteListenerChecker, path: &str) -> (String, HashMap<String, String>) {
+        let mut labels = HashMap::new();
+        let value = checker.apply_template(path, &mut labels);
+        (value, labels)
+    }
+
+    fn labels(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(l, v)| (String::from(*l), String::from(*v))).collect()
+    }
+
+    #[test]
+    fn statsd_types() {
+        let checker = checker(&[]);
+        assert_eq!(statsd(&checker, "requests:3|c").map(|r| r.1), Some(3.0));
+        assert_eq!(statsd(&checker, "requests:3|c|@0.1").map(|r| r.1), Some(30.0));
+        assert_eq!(statsd(&checker, "latency:12.5|ms|@0.5").map(|r| r.1), Some(12.5));
+        assert_eq!(statsd(&checker, "size:100|h").map(|r| r.1), Some(100.0));
+        assert_eq!(statsd(&checker, "size:100|d").map(|r| r.1), Some(100.0));
+    }
+
+    #[test]
+    fn statsd_gauges() {
+        let checker = checker(&[]);
+        assert_eq!(statsd(&checker, "queue:10|g").map(|r| r.1), Some(10.0));
+        assert_eq!(statsd(&checker, "queue:+5|g").map(|r| r.1), Some(15.0));
+        assert_eq!(statsd(&checker, "queue:-20|g").map(|r| r.1), Some(-5.0));
+        assert_eq!(statsd(&checker, "queue:7|g").map(|r| r.1), Some(7.0));
+        assert_eq!(statsd(&checker, "other:+1|g").map(|r| r.1), Some(1.0));
+    }
+
+    #[test]
+    fn statsd_tags() {
+        let checker = checker(&[]);
+        let (name, value, tags) = statsd(&checker, "db.query:2|c|#env:prod,canary,probe_name:x").unwrap();
+        assert_eq!(name, "db.query");
+        assert_eq!(value, 2.0);
+        assert_eq!(tags, labels(&[("env", "prod"), ("canary", ""), ("probe_name", "x")]));
+    }
+
+    #[test]
+    fn statsd_invalid() {
+        let checker = checker(&[]);
+        assert!(statsd(&checker, "requests").is_none());
+        assert!(statsd(&checker, "requests:1").is_none());
+        assert!(statsd(&checker, "requests:x|c").is_none());
+        assert!(statsd(&checker, "users:bob|s").is_none());
+        assert!(statsd(&checker, "requests:1|c|@0").is_none());
+        assert!(statsd(&checker, "requests:1|c|@fast").is_none());
+    }
+
+    #[test]
+    fn templates() {
+        let checker = checker(&[
+            (Some("servers.*.disk"), ".host..device.value"),
+            (Some("servers"), ".host.value*"),
+            (None, "app.value")]);
+        assert_eq!(template(&checker, "servers.web1.cpu.user"), (String::from("cpu.user"), labels(&[("host", "web1")])));
+        assert_eq!(template(&checker, "servers.web1.disk.sda.free.bytes"),
+            (String::from("free.bytes"), labels(&[("host", "web1"), ("device", "sda")])));
+        assert_eq!(template(&checker, "billing.requests"), (String::from("requests"), labels(&[("app", "billing")])));
+        // Path shorter than the template
+        assert_eq!(template(&checker, "billing"), (String::from("value"), labels(&[("app", "billing")])));
+        // Filter matches whole segments only
+        assert_eq!(template(&checker, "serversx.web1.cpu"), (String::from("web1.cpu"), labels(&[("app", "serversx")])));
+    }
+
+    #[test]
+    fn no_matching_template() {
+        let checker = checker(&[(Some("servers"), ".host.value*")]);
+        assert_eq!(template(&checker, "app.requests"), (String::from("app.requests"), HashMap::new()));
+    }
+}
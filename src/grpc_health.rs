@@ -0,0 +1,94 @@
+use crate::config::{ProbeConfig, get_duration};
+use crate::scheduler::Scheduler;
+use yaml_rust::Yaml;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+use crate::checker::CheckResult;
+use std::collections::HashMap;
+use tonic::transport::Endpoint;
+use log::{debug, error};
+
+use health::health_client::HealthClient;
+use health::HealthCheckRequest;
+use health::health_check_response::ServingStatus;
+pub mod health {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+pub struct GrpcHealthChecker {
+    host: String,
+    scheduler: Scheduler,
+    timeout: Duration,
+    service: String,
+    name: String,
+    precision: i64,
+    labels: HashMap<String, String>
+}
+
+impl GrpcHealthChecker {
+    pub fn new(config: &ProbeConfig) -> Self {
+        Self{
+            name: config.name.clone(),
+            host: config.host.clone(),
+            scheduler: Scheduler::new(config),
+            timeout: get_duration(&config.config, "timeout", Duration::from_secs(1)),
+            service: config.config.get("service")
+                .unwrap_or(&Yaml::String(String::new()))
+                .clone()
+                .into_string()
+                .unwrap(),
+            precision: config.config.get("precision")
+                .unwrap_or(&Yaml::Integer(1))
+                .clone()
+                .into_i64()
+                .unwrap(),
+            labels: config.labels.clone()
+        }
+    }
+
+    fn emit(&self, sender: &Sender<CheckResult>, status: ServingStatus, value_name: &str, value: f32) {
+        let mut to_emit = CheckResult{
+            name: self.name.clone(),
+            values: HashMap::new(),
+            processes: Vec::new(),
+            labels: self.labels.clone()};
+        to_emit.labels.insert(String::from("status"), String::from(status.as_str_name()));
+        to_emit.values.insert(String::from(value_name), value);
+        sender.send(to_emit).unwrap();
+    }
+}
+
+#[tokio::main]
+pub async fn grpc_health(mut checker: GrpcHealthChecker, sender: Sender<CheckResult>) {
+    let timeout = checker.timeout;
+    let uri = if checker.host.contains("://") {
+        checker.host.clone()
+    } else {
+        format!("http://{}", checker.host)
+    };
+    let endpoint = match Endpoint::from_shared(uri) {
+        Ok(e) => e.connect_timeout(timeout).timeout(timeout),
+        Err(e) => {
+            error!("Invalid grpc_health address {}: {}", checker.host, e);
+            return;
+        }
+    };
+    let mut client = HealthClient::new(endpoint.connect_lazy());
+    loop {
+        tokio::time::sleep(checker.scheduler.delay()).await;
+        let request = tonic::Request::new(HealthCheckRequest{service: checker.service.clone()});
+        let start = Instant::now();
+        match client.check(request).await {
+            Ok(response) => {
+                let rtt = Instant::now().duration_since(start);
+                let status = ServingStatus::from_i32(response.into_inner().status).unwrap_or(ServingStatus::Unknown);
+                checker.emit(&sender, status, "rtt", (rtt.as_micros() as f32) / checker.precision as f32);
+                checker.emit(&sender, status, "loss", 0.0);
+            },
+            Err(e) => {
+                debug!("Health check to {} failed: {}", checker.host, e);
+                checker.emit(&sender, ServingStatus::Unknown, "loss", 1.0);
+            }
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use crate::checker::CheckResult;
 use crate::process::Processes;
-use crate::config::ProcessConfig;
+use crate::config::{ProcessConfig, get_required_duration};
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
@@ -14,16 +14,15 @@ pub struct Histogram {
     stats: Arc<Mutex<HashMap<String, Vec<f32>>>>,
     probes: Arc<Mutex<HashMap<String, CheckResult>>>,
     to_process: String,
-    interval: i32,
+    interval: Duration,
     labels_to_add: HashMap<String, String>,
     receiver: Receiver<CheckResult>,
     id: u16
 }
 
-fn emit_probes(interval: i32, stats: Arc<Mutex<HashMap<String, Vec<f32>>>>, probes: Arc<Mutex<HashMap<String, CheckResult>>>, sender: Sender<CheckResult>) {
-    let duration = Duration::from_secs(interval.try_into().unwrap());
+fn emit_probes(interval: Duration, stats: Arc<Mutex<HashMap<String, Vec<f32>>>>, probes: Arc<Mutex<HashMap<String, CheckResult>>>, sender: Sender<CheckResult>) {
     loop {
-        sleep(duration);
+        sleep(interval);
         let mut to_emit_stats = stats.lock().unwrap();
         let to_emit_probes = probes.lock().unwrap();
         let mut n_probe: i32 = 0;
@@ -51,14 +50,14 @@ impl Histogram {
             stats: Arc::new(Mutex::new(HashMap::new())),
             probes: Arc::new(Mutex::new(HashMap::new())),
             to_process: config.match_value.clone(),
-            interval: config.config.get("interval").unwrap().clone().into_i64().unwrap() as i32,
+            interval: get_required_duration(&config.config, "interval"),
             id: config.id.clone(),
             labels_to_add: config.labels_to_add.clone(),
             receiver: receiver
         };
         let stats = Arc::clone(&result.stats);
         let probes = Arc::clone(&result.probes);
-        let interval = result.interval.clone();
+        let interval = result.interval;
         thread::spawn(move || { emit_probes(interval, stats, probes, sender) });
         return result
     }
@@ -0,0 +1,206 @@
+use crate::config::ProbeConfig;
+use crate::json_input::parse_probe;
+use yaml_rust::Yaml;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::net::{TcpListener, TcpStream};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::checker::CheckResult;
+use std::collections::HashMap;
+use serde_json::Value;
+use log::{debug, error};
+
+// Whole request has to arrive within the timeout, slow clients are cut off
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_LINE: usize = 8192;
+const MAX_HEADERS: usize = 100;
+const MAX_BODY: usize = 1024 * 1024;
+const MAX_CONNECTIONS: usize = 64;
+
+pub struct HttpListenerChecker {
+    listen: String,
+    path: String,
+    token: Option<String>,
+    labels: HashMap<String, String>
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        _ => "Error"
+    }
+}
+
+// Stream reads sharing one deadline, every read waits only for the time left
+struct DeadlineStream<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant
+}
+
+impl Read for DeadlineStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "request timed out"));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+// Comparison time doesn't depend on where the strings differ, only on their lengths
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Line of request head, at most MAX_LINE bytes
+fn read_line(reader: &mut BufReader<DeadlineStream>, line: &mut String) -> Result<(), (u16, String)> {
+    line.clear();
+    reader.by_ref().take(MAX_LINE as u64).read_line(line).map_err(|e| (400, e.to_string()))?;
+    if !line.ends_with('\n') {
+        if line.len() >= MAX_LINE {
+            return Err((431, format!("line is longer than {} bytes", MAX_LINE)));
+        }
+        return Err((400, String::from("incomplete request")));
+    }
+    Ok(())
+}
+
+impl HttpListenerChecker {
+    pub fn new(config: &ProbeConfig) -> Self {
+        Self{
+            listen: config.host.clone(),
+            path: config.config.get("path")
+                .unwrap_or(&Yaml::String(String::from("/probes")))
+                .clone()
+                .into_string()
+                .unwrap(),
+            token: config.config.get("token").map(|t| t.clone().into_string().unwrap()),
+            labels: config.labels.clone()
+        }
+    }
+
+    // Parse POST request with a probe object or an array of them. The whole batch is rejected
+    // if any probe in it is invalid
+    fn read_request(&self, mut stream: &TcpStream) -> Result<Vec<CheckResult>, (u16, String)> {
+        let mut reader = BufReader::new(DeadlineStream{stream, deadline: Instant::now() + REQUEST_TIMEOUT});
+        let mut line = String::new();
+        read_line(&mut reader, &mut line)?;
+        let mut request = line.split_whitespace();
+        let method = request.next().unwrap_or_default().to_string();
+        let target = request.next().unwrap_or_default().to_string();
+        let mut headers = HashMap::new();
+        loop {
+            read_line(&mut reader, &mut line)?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if headers.len() >= MAX_HEADERS {
+                return Err((400, String::from("too many headers")));
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+            }
+        }
+        if target.split('?').next().unwrap_or_default() != self.path {
+            return Err((404, format!("no such path {}", target)));
+        }
+        if method != "POST" {
+            return Err((405, String::from("only POST is allowed")));
+        }
+        if let Some(token) = &self.token {
+            let presented = headers.get("authorization")
+                .and_then(|a| a.strip_prefix("Bearer "))
+                .unwrap_or_default();
+            if !constant_time_eq(presented.as_bytes(), token.as_bytes()) {
+                return Err((401, String::from("invalid token")));
+            }
+        }
+        let length = headers.get("content-length")
+            .and_then(|l| l.parse::<usize>().ok())
+            .ok_or((411, String::from("no content-length")))?;
+        if length > MAX_BODY {
+            return Err((413, format!("body is larger than {} bytes", MAX_BODY)));
+        }
+        if headers.get("expect").map(|e| e.eq_ignore_ascii_case("100-continue")).unwrap_or(false) {
+            _ = stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+        }
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).map_err(|e| (400, e.to_string()))?;
+        let probes = match serde_json::from_slice::<Value>(&body).map_err(|e| (400, e.to_string()))? {
+            Value::Array(probes) => probes,
+            probe => vec![probe]
+        };
+        let mut result = Vec::new();
+        for (index, probe) in probes.iter().enumerate() {
+            result.push(parse_probe(probe, &self.labels).map_err(|e| (400, format!("probe {}: {}", index, e)))?);
+        }
+        Ok(result)
+    }
+
+    fn handle(&self, mut stream: TcpStream, sender: &Sender<CheckResult>) {
+        _ = stream.set_write_timeout(Some(REQUEST_TIMEOUT));
+        let (status, message) = match self.read_request(&stream) {
+            Ok(probes) => {
+                let count = probes.len();
+                for probe in probes {
+                    sender.send(probe).unwrap();
+                }
+                (200, format!("accepted {}", count))
+            },
+            Err((status, message)) => {
+                debug!("Rejected request from {:?}: {}", stream.peer_addr(), message);
+                (status, message)
+            }
+        };
+        let response = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+            status, reason(status), message.len() + 1, message);
+        _ = stream.write_all(response.as_bytes());
+    }
+}
+
+pub fn http_listener(checker: HttpListenerChecker, sender: Sender<CheckResult>) {
+    let listener = match TcpListener::bind(&checker.listen) {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Failed to listen on {}: {}", checker.listen, e);
+            return;
+        }
+    };
+    let checker = Arc::new(checker);
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if connections.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+                    debug!("Too many connections, dropping {:?}", stream.peer_addr());
+                    continue;
+                }
+                connections.fetch_add(1, Ordering::SeqCst);
+                let checker = Arc::clone(&checker);
+                let connections = Arc::clone(&connections);
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    checker.handle(stream, &sender);
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                });
+            },
+            Err(e) => debug!("Failed to accept connection: {}", e)
+        }
+    }
+}
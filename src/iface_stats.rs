@@ -0,0 +1,145 @@
+use crate::config::ProbeConfig;
+use crate::scheduler::Scheduler;
+use crate::rate::RateTracker;
+use std::sync::mpsc::Sender;
+use std::fs;
+use std::process;
+use std::time::Instant;
+use crate::checker::CheckResult;
+use std::collections::HashMap;
+use log::{debug, error};
+
+// /proc/net/dev columns, named as in /sys/class/net/<interface>/statistics
+const PROC_NET_DEV_COUNTERS: [&str; 16] = [
+    "rx_bytes", "rx_packets", "rx_errors", "rx_dropped", "rx_fifo_errors", "rx_frame_errors", "rx_compressed", "multicast",
+    "tx_bytes", "tx_packets", "tx_errors", "tx_dropped", "tx_fifo_errors", "collisions", "tx_carrier_errors", "tx_compressed"];
+const DEFAULT_COUNTERS: [&str; 10] = [
+    "rx_bytes", "rx_packets", "rx_errors", "rx_dropped", "rx_fifo_errors",
+    "tx_bytes", "tx_packets", "tx_errors", "tx_dropped", "tx_fifo_errors"];
+
+pub struct IfaceStatsChecker {
+    scheduler: Scheduler,
+    interfaces: Vec<String>,
+    counters: Vec<String>,
+    name: String,
+    labels: HashMap<String, String>
+}
+
+impl IfaceStatsChecker {
+    pub fn new(config: &ProbeConfig) -> Self {
+        let mut result = Self{
+            name: config.name.clone(),
+            scheduler: Scheduler::new(config),
+            interfaces: Vec::new(),
+            counters: Vec::new(),
+            labels: config.labels.clone()
+        };
+        if let Some(yaml_rust::Yaml::Array(ref interfaces)) = config.config.get("interfaces") {
+            for interface in interfaces {
+                result.interfaces.push(interface.clone().into_string().unwrap());
+            }
+        }
+        match config.config.get("counters") {
+            Some(yaml_rust::Yaml::Array(ref counters)) => {
+                for counter in counters {
+                    result.counters.push(counter.clone().into_string().unwrap());
+                }
+            },
+            _ => {
+                result.counters = DEFAULT_COUNTERS.iter().map(|c| String::from(*c)).collect();
+            }
+        }
+        // sysfs shows the interfaces of the namespace it was mounted in, not the probe netns
+        if config.config.contains_key("netns") {
+            if let Some(counter) = result.counters.iter().find(|c| !PROC_NET_DEV_COUNTERS.contains(&c.as_str())) {
+                error!("Counter {} of {} is read from sysfs and is not supported with netns", counter, config.name);
+                process::exit(1);
+            }
+        }
+        result
+    }
+
+    fn emit(&self, sender: &Sender<CheckResult>, interface: &str, value_name: &str, value: f32) {
+        let mut to_emit = CheckResult{
+            name: self.name.clone(),
+            values: HashMap::new(),
+            processes: Vec::new(),
+            labels: self.labels.clone()};
+        to_emit.labels.insert(String::from("interface"), String::from(interface));
+        to_emit.values.insert(String::from(value_name), value);
+        sender.send(to_emit).unwrap();
+    }
+}
+
+// Counters of every interface. Read through thread-self, so probes in a netns see their own interfaces
+fn read_proc_net_dev() -> Option<HashMap<String, Vec<u64>>> {
+    let text = match fs::read_to_string("/proc/thread-self/net/dev") {
+        Ok(t) => t,
+        Err(e) => {
+            error!("Failed to read /proc/net/dev: {}", e);
+            return None;
+        }
+    };
+    let mut result = HashMap::new();
+    // Two header lines
+    for line in text.lines().skip(2) {
+        if let Some((interface, counters)) = line.split_once(':') {
+            result.insert(
+                String::from(interface.trim()),
+                counters.split_whitespace().filter_map(|c| c.parse::<u64>().ok()).collect());
+        }
+    }
+    Some(result)
+}
+
+fn read_sysfs(interface: &str, counter: &str) -> Option<u64> {
+    fs::read_to_string(format!("/sys/class/net/{}/statistics/{}", interface, counter))
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+}
+
+pub fn iface_stats(mut checker: IfaceStatsChecker, sender: Sender<CheckResult>) {
+    let mut rates = RateTracker::default();
+    loop {
+        checker.scheduler.wait();
+        let devices = match read_proc_net_dev() {
+            Some(d) => d,
+            None => continue
+        };
+        let now = Instant::now();
+        let mut interfaces: Vec<&String> = if checker.interfaces.is_empty() {
+            devices.keys().collect()
+        } else {
+            checker.interfaces.iter().collect()
+        };
+        interfaces.sort();
+        for interface in interfaces {
+            let device = match devices.get(interface) {
+                Some(d) => d,
+                None => {
+                    debug!("Interface {} not found", interface);
+                    continue;
+                }
+            };
+            for counter in &checker.counters {
+                // Counters missing from /proc/net/dev, e.g. rx_crc_errors, come from sysfs
+                let value = match PROC_NET_DEV_COUNTERS.iter().position(|c| c == counter) {
+                    Some(i) => device.get(i).copied(),
+                    None => read_sysfs(interface, counter)
+                };
+                let value = match value {
+                    Some(v) => v,
+                    None => {
+                        debug!("Counter {} not found for {}", counter, interface);
+                        continue;
+                    }
+                };
+                if let Some(rate) = rates.update(&format!("{}/{}", interface, counter), value, now) {
+                    checker.emit(&sender, interface, counter, rate);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use crate::checker::CheckResult;
+use crate::resolver::Resolver;
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::Sender;
+use log::debug;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+// Bind socket to interface or VRF device
+pub fn bind_to_device(fd: RawFd, interface: &str) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            interface.as_ptr() as *const libc::c_void,
+            interface.len() as libc::socklen_t)
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Parse source_ip option
+pub fn source_ipv4(source_ip: &str) -> Option<Ipv4Addr> {
+    match format!("{}:0", source_ip).to_socket_addrs().ok()?.next()?.ip() {
+        IpAddr::V4(ip) => Some(ip),
+        IpAddr::V6(_) => None
+    }
+}
+
+// Parse source option, <address> or <address>:<port>
+pub fn source_addr(source: &str) -> Option<SocketAddr> {
+    match source.parse::<IpAddr>() {
+        Ok(ip) => Some(SocketAddr::new(ip, 0)),
+        Err(_) => source.to_socket_addrs().ok()?.next()
+    }
+}
+
+// Parse list of source_ip options, the invalid one is returned as error
+pub fn source_ipv4s(source_ips: &[String]) -> Result<Vec<Ipv4Addr>, String> {
+    source_ips.iter()
+        .map(|s| source_ipv4(s).ok_or(s.clone()))
+        .collect()
+}
+
+// IPv4 targets paired with every configured source, or the one the kernel would pick, and their labels
+// including source. Targets without a route are reported as lost
+pub fn probe_targets(name: &str, resolver: &Resolver, labels: &HashMap<String, String>, sources: &[Ipv4Addr],
+                     bind_interface: &Option<String>, sender: &Sender<CheckResult>) -> Vec<(Ipv4Addr, Ipv4Addr, HashMap<String, String>)> {
+    let mut result = Vec::new();
+    for target in resolver.addresses() {
+        let addr = match target.ip() {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => continue
+        };
+        let labels = resolver.labels(labels, &target);
+        let saddrs = if sources.is_empty() {
+            match route_source(addr, bind_interface) {
+                Ok(ip) => vec![ip],
+                Err(e) => {
+                    debug!("No source address for {}: {}", addr, e);
+                    let mut to_emit = CheckResult{
+                        name: String::from(name),
+                        values: HashMap::new(),
+                        processes: Vec::new(),
+                        labels};
+                    to_emit.values.insert(String::from("loss"), 1.0);
+                    sender.send(to_emit).unwrap();
+                    continue;
+                }
+            }
+        } else {
+            sources.to_vec()
+        };
+        for saddr in saddrs {
+            let mut labels = labels.clone();
+            labels.insert(String::from("source"), saddr.to_string());
+            result.push((addr, saddr, labels));
+        }
+    }
+    result
+}
+
+// Source address the kernel would pick to reach target, within bind_interface if set.
+// Connecting a UDP socket does the route lookup without sending anything
+pub fn route_source(target: Ipv4Addr, bind_interface: &Option<String>) -> io::Result<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    if let Some(interface) = bind_interface {
+        bind_to_device(socket.as_raw_fd(), interface)?;
+    }
+    socket.connect((target, 9))?;
+    match socket.local_addr()?.ip() {
+        IpAddr::V4(ip) => Ok(ip),
+        IpAddr::V6(_) => Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "no IPv4 source"))
+    }
+}
@@ -0,0 +1,187 @@
+use crate::config::ProbeConfig;
+use yaml_rust::Yaml;
+use std::sync::mpsc::Sender;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::thread;
+use std::time::Duration;
+use crate::checker::CheckResult;
+use std::collections::HashMap;
+use serde_json::Value;
+use log::{debug, error, info};
+
+pub struct JsonInputChecker {
+    path: String,
+    from_start: bool,
+    interval: Duration,
+    labels: HashMap<String, String>
+}
+
+// Build result from {"name": ..., "labels": {...}, "values": {...}}. Labels of the object
+// override probe labels
+pub fn parse_probe(value: &Value, labels: &HashMap<String, String>) -> Result<CheckResult, String> {
+    let object = value.as_object().ok_or("not an object")?;
+    let name = object.get("name")
+        .ok_or("no name")?
+        .as_str()
+        .ok_or("name is not a string")?;
+    let mut result = CheckResult{
+        name: String::from(name),
+        values: HashMap::new(),
+        processes: Vec::new(),
+        labels: labels.clone()};
+    if let Some(object_labels) = object.get("labels") {
+        for (label, label_value) in object_labels.as_object().ok_or("labels is not an object")? {
+            let label_value = match label_value {
+                Value::String(s) => s.clone(),
+                Value::Number(n) => n.to_string(),
+                Value::Bool(b) => b.to_string(),
+                _ => return Err(format!("label {} is not a string", label))
+            };
+            result.labels.insert(label.clone(), label_value);
+        }
+    }
+    for (value_name, value) in object.get("values").ok_or("no values")?.as_object().ok_or("values is not an object")? {
+        let value = value.as_f64().ok_or(format!("value {} is not a number", value_name))?;
+        result.values.insert(value_name.clone(), value as f32);
+    }
+    if result.values.is_empty() {
+        return Err(String::from("values is empty"));
+    }
+    Ok(result)
+}
+
+impl JsonInputChecker {
+    pub fn new(config: &ProbeConfig) -> Self {
+        Self{
+            path: config.config.get("path")
+                .unwrap_or(&Yaml::String(String::from("-")))
+                .clone()
+                .into_string()
+                .unwrap(),
+            from_start: config.config.get("from_start")
+                .unwrap_or(&Yaml::Boolean(false))
+                .clone()
+                .as_bool()
+                .unwrap(),
+            interval: config.interval,
+            labels: config.labels.clone()
+        }
+    }
+
+    fn process_line(&self, sender: &Sender<CheckResult>, line: &[u8]) {
+        if line.iter().all(|c| c.is_ascii_whitespace()) {
+            return;
+        }
+        let parsed = serde_json::from_slice::<Value>(line)
+            .map_err(|e| e.to_string())
+            .and_then(|v| parse_probe(&v, &self.labels));
+        match parsed {
+            Ok(result) => sender.send(result).unwrap(),
+            Err(e) => error!("{}: invalid probe {}: {}", self.path, String::from_utf8_lossy(line).trim(), e)
+        }
+    }
+
+    // Read lines until all writers are gone
+    fn read_stream<R: BufRead>(&self, sender: &Sender<CheckResult>, mut reader: R) {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => return,
+                Ok(_) => {
+                    self.process_line(sender, &line);
+                },
+                Err(e) => {
+                    error!("Failed to read {}: {}", self.path, e);
+                    return;
+                }
+            }
+        }
+    }
+
+    // tail -f: wait for lines appended to the file, reopen it when it's replaced by rotation,
+    // start over when it's truncated
+    fn tail(&self, sender: &Sender<CheckResult>) {
+        let mut from_start = self.from_start;
+        loop {
+            let file = match File::open(&self.path) {
+                Ok(f) => f,
+                Err(e) => {
+                    debug!("Failed to open {}: {}", self.path, e);
+                    thread::sleep(self.interval);
+                    continue;
+                }
+            };
+            let inode = file.metadata().map(|m| m.ino()).unwrap_or_default();
+            let mut reader = BufReader::new(file);
+            let mut position = if from_start {
+                0
+            } else {
+                reader.seek(SeekFrom::End(0)).unwrap_or_default()
+            };
+            // File created by rotation is read from the beginning
+            from_start = true;
+            let mut line = Vec::new();
+            loop {
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) => {
+                        match fs::metadata(&self.path) {
+                            Ok(m) if m.ino() != inode => {
+                                debug!("{} rotated", self.path);
+                                break;
+                            },
+                            Ok(m) if m.len() < position => {
+                                debug!("{} truncated", self.path);
+                                _ = reader.seek(SeekFrom::Start(0));
+                                position = 0;
+                                line.clear();
+                            },
+                            _ => {}
+                        }
+                        thread::sleep(self.interval);
+                    },
+                    Ok(size) => {
+                        position += size as u64;
+                        // Incomplete line is kept until the writer finishes it
+                        if line.ends_with(b"\n") {
+                            self.process_line(sender, &line);
+                            line.clear();
+                        }
+                    },
+                    Err(e) => {
+                        error!("Failed to read {}: {}", self.path, e);
+                        thread::sleep(self.interval);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn json_input(checker: JsonInputChecker, sender: Sender<CheckResult>) {
+    if checker.path == "-" {
+        checker.read_stream(&sender, io::stdin().lock());
+        info!("End of stdin, json_input stopped");
+        return;
+    }
+    let is_fifo = fs::metadata(&checker.path)
+        .map(|m| m.file_type().is_fifo())
+        .unwrap_or(false);
+    if !is_fifo {
+        checker.tail(&sender);
+        return;
+    }
+    // Opening a named pipe blocks until a writer appears, read again after every writer leaves
+    loop {
+        match File::open(&checker.path) {
+            Ok(f) => checker.read_stream(&sender, BufReader::new(f)),
+            Err(e) => {
+                error!("Failed to open {}: {}", checker.path, e);
+                thread::sleep(checker.interval);
+            }
+        }
+    }
+}
@@ -17,6 +17,29 @@ pub mod output_graphite;
 pub mod mtu_pinger;
 pub mod udp_server;
 pub mod udp_client;
+pub mod resolver;
+pub mod scheduler;
+pub mod interface;
+pub mod netns;
+pub mod timestamp;
+pub mod rate;
+pub mod ntp;
+pub mod exec;
+pub mod arp_pinger;
+pub mod passive_tcp;
+pub mod grpc_health;
+pub mod tcp_expect;
+pub mod iface_stats;
+pub mod netstat;
+pub mod json_input;
+pub mod http_listener;
+pub mod graphite_listener;
+pub mod ber;
+pub mod usm;
+pub mod snmp;
+pub mod scrape;
+pub mod dhcp;
+pub mod encap;
 
 use log::{info, debug};
 use crate::config::load_config;
@@ -27,6 +50,20 @@ use crate::syn_pinger::{SynChecker, syn_sender, syn_receiver};
 use crate::tcp_connect::{TcpConnectChecker, tcp_connect};
 use crate::udp_server::{UdpServerChecker, udp_server};
 use crate::udp_client::{UdpClientChecker, udp_client};
+use crate::ntp::{NtpChecker, ntp};
+use crate::exec::{ExecChecker, exec};
+use crate::arp_pinger::{ArpChecker, arp_pinger};
+use crate::passive_tcp::{PassiveTcpChecker, passive_tcp};
+use crate::grpc_health::{GrpcHealthChecker, grpc_health};
+use crate::tcp_expect::{TcpExpectChecker, tcp_expect};
+use crate::iface_stats::{IfaceStatsChecker, iface_stats};
+use crate::netstat::{NetstatChecker, netstat};
+use crate::json_input::{JsonInputChecker, json_input};
+use crate::http_listener::{HttpListenerChecker, http_listener};
+use crate::graphite_listener::{GraphiteListenerChecker, graphite_listener};
+use crate::snmp::{SnmpChecker, snmp};
+use crate::scrape::{ScrapeChecker, scrape};
+use crate::dhcp::{DhcpChecker, dhcp};
 use crate::selector::selector_worker;
 use crate::stats_process::StatsCount;
 use crate::stats_time_process::StatsTime;
@@ -116,48 +153,135 @@ fn main() {
             let checker = Arc::new(IcmpChecker::new(&new_check));
             let sender = Arc::clone(&checker);
             let sender_tx = selector_tx.clone();
-            let rcv = thread::spawn(move || {icmp_receiver(&sender, sender_tx)});
+            let rcv = netns::spawn(&new_check, move || {icmp_receiver(&sender, sender_tx)});
             pinger_handles.push(rcv);
             let sender = Arc::clone(&checker);
-            let rcv = thread::spawn(move || {icmp_sender(&sender)});
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {icmp_sender(&sender, sender_tx)});
             pinger_handles.push(rcv);
         } else if new_check.check_type == "mtu_icmp" {
             info!("  Starting mtu_icmp for {}", new_check.host);
             let checker = Arc::new(IcmpMtuChecker::new(&new_check));
             let sender = Arc::clone(&checker);
             let sender_tx = selector_tx.clone();
-            let rcv = thread::spawn(move || {icmp_mtu_receiver(&sender, sender_tx)});
+            let rcv = netns::spawn(&new_check, move || {icmp_mtu_receiver(&sender, sender_tx)});
             pinger_handles.push(rcv);
             let sender = Arc::clone(&checker);
-            let rcv = thread::spawn(move || {icmp_mtu_sender(&sender)});
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {icmp_mtu_sender(&sender, sender_tx)});
             pinger_handles.push(rcv);
         } else if new_check.check_type == "syn" {
             info!("  Starting syn for {}", new_check.host);
             let checker = Arc::new(SynChecker::new(&new_check));
             let sender = Arc::clone(&checker);
             let sender_tx = selector_tx.clone();
-            let rcv = thread::spawn(move || {syn_receiver(&sender, sender_tx)});
+            let rcv = netns::spawn(&new_check, move || {syn_receiver(&sender, sender_tx)});
             pinger_handles.push(rcv);
             let sender = Arc::clone(&checker);
-            let rcv = thread::spawn(move || {syn_sender(&sender)});
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {syn_sender(&sender, sender_tx)});
             pinger_handles.push(rcv);
         } else if new_check.check_type == "tcp_connect" {
             info!("  Starting tcp_connect for {}", new_check.host);
             let checker = TcpConnectChecker::new(&new_check);
             let sender_tx = selector_tx.clone();
-            let rcv = thread::spawn(move || {tcp_connect(checker, sender_tx)});
+            let rcv = netns::spawn(&new_check, move || {tcp_connect(checker, sender_tx)});
             pinger_handles.push(rcv);
         } else if new_check.check_type == "udp_server" {
             info!("  Starting udp server for {}", new_check.host);
             let checker = UdpServerChecker::new(&new_check);
             let sender_tx = selector_tx.clone();
-            let rcv = thread::spawn(move || {udp_server(checker, sender_tx)});
+            let rcv = netns::spawn(&new_check, move || {udp_server(checker, sender_tx)});
             pinger_handles.push(rcv);
         } else if new_check.check_type == "udp_client" {
             info!("  Starting udp client for {}", new_check.host);
             let checker = UdpClientChecker::new(&new_check);
             let sender_tx = selector_tx.clone();
-            let rcv = thread::spawn(move || {udp_client(checker, sender_tx)});
+            let rcv = netns::spawn(&new_check, move || {udp_client(checker, sender_tx)});
+            pinger_handles.push(rcv);
+        } else if new_check.check_type == "ntp" {
+            info!("  Starting ntp for {}", new_check.host);
+            let checker = NtpChecker::new(&new_check);
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {ntp(checker, sender_tx)});
+            pinger_handles.push(rcv);
+        } else if new_check.check_type == "exec" {
+            info!("  Starting exec for {}", new_check.name);
+            let checker = ExecChecker::new(&new_check);
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {exec(checker, sender_tx)});
+            pinger_handles.push(rcv);
+        } else if new_check.check_type == "arp" {
+            info!("  Starting arp for {}", new_check.host);
+            let checker = ArpChecker::new(&new_check);
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {arp_pinger(checker, sender_tx)});
+            pinger_handles.push(rcv);
+        } else if new_check.check_type == "passive_tcp" {
+            info!("  Starting passive_tcp for {}", new_check.name);
+            let checker = PassiveTcpChecker::new(&new_check);
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {passive_tcp(checker, sender_tx)});
+            pinger_handles.push(rcv);
+        } else if new_check.check_type == "grpc_health" {
+            info!("  Starting grpc_health for {}", new_check.host);
+            let checker = GrpcHealthChecker::new(&new_check);
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {grpc_health(checker, sender_tx)});
+            pinger_handles.push(rcv);
+        } else if new_check.check_type == "tcp_expect" {
+            info!("  Starting tcp_expect for {}", new_check.host);
+            let checker = TcpExpectChecker::new(&new_check);
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {tcp_expect(checker, sender_tx)});
+            pinger_handles.push(rcv);
+        } else if new_check.check_type == "iface_stats" {
+            info!("  Starting iface_stats for {}", new_check.name);
+            let checker = IfaceStatsChecker::new(&new_check);
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {iface_stats(checker, sender_tx)});
+            pinger_handles.push(rcv);
+        } else if new_check.check_type == "netstat" {
+            info!("  Starting netstat for {}", new_check.name);
+            let checker = NetstatChecker::new(&new_check);
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {netstat(checker, sender_tx)});
+            pinger_handles.push(rcv);
+        } else if new_check.check_type == "json_input" {
+            info!("  Starting json_input for {}", new_check.name);
+            let checker = JsonInputChecker::new(&new_check);
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {json_input(checker, sender_tx)});
+            pinger_handles.push(rcv);
+        } else if new_check.check_type == "http_listener" {
+            info!("  Starting http_listener for {}", new_check.name);
+            let checker = HttpListenerChecker::new(&new_check);
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {http_listener(checker, sender_tx)});
+            pinger_handles.push(rcv);
+        } else if new_check.check_type == "graphite_listener" {
+            info!("  Starting graphite_listener for {}", new_check.name);
+            let checker = GraphiteListenerChecker::new(&new_check);
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {graphite_listener(checker, sender_tx)});
+            pinger_handles.push(rcv);
+        } else if new_check.check_type == "snmp" {
+            info!("  Starting snmp for {}", new_check.host);
+            let checker = SnmpChecker::new(&new_check);
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {snmp(checker, sender_tx)});
+            pinger_handles.push(rcv);
+        } else if new_check.check_type == "scrape" {
+            info!("  Starting scrape for {}", new_check.host);
+            let checker = ScrapeChecker::new(&new_check);
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {scrape(checker, sender_tx)});
+            pinger_handles.push(rcv);
+        } else if new_check.check_type == "dhcp" {
+            info!("  Starting dhcp for {}", new_check.name);
+            let checker = DhcpChecker::new(&new_check);
+            let sender_tx = selector_tx.clone();
+            let rcv = netns::spawn(&new_check, move || {dhcp(checker, sender_tx)});
             pinger_handles.push(rcv);
         } else if new_check.check_type == "remote_listener" {
             info!("  Starting remote_listener");
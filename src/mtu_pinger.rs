@@ -1,10 +1,13 @@
 extern crate pnet;
 
 use log::{debug, error};
-use crate::config::ProbeConfig;
+use crate::config::{ProbeConfig, get_strings, get_required_duration};
+use crate::resolver::Resolver;
+use crate::scheduler::Scheduler;
+use crate::interface::{bind_to_device, probe_targets, source_ipv4s};
 use std::sync::{Arc, Mutex};
 use yaml_rust::Yaml;
-use std::net::{IpAddr, ToSocketAddrs};
+use std::net::IpAddr;
 use std::thread;
 use std::time::{Duration, Instant};
 use std::sync::mpsc::Sender;
@@ -21,11 +24,12 @@ use std::collections::HashMap;
 use crate::checker::CheckResult;
 
 pub struct IcmpMtuChecker {
-    host: String,
+    resolver: Mutex<Resolver>,
     mtu: Vec<i64>,
-    interval: i64,
-    mtu_interval: i64,
-    source_ip: String,
+    scheduler: Mutex<Scheduler>,
+    mtu_interval: Duration,
+    source_ip: Vec<String>,
+    bind_interface: Option<String>,
     name: String,
     probes: Mutex<Vec<Probe>>,
     precision: i64,
@@ -36,6 +40,8 @@ struct Probe {
     identifier: u16,
     seq: u16,
     mtu: i64,
+    addr: IpAddr,
+    labels: HashMap<String, String>,
     sent: Instant
 }
 
@@ -43,11 +49,12 @@ impl IcmpMtuChecker {
     pub fn new(config: &ProbeConfig) -> Self {
         let mut result = Self{
                             name: config.name.clone(),
-                            host: config.host.clone(),
+                            resolver: Mutex::new(Resolver::new(config, format!("{}:0", config.host))),
                             mtu: Vec::new(),
-                            interval: config.interval.clone(),
-                            mtu_interval: config.config.get("interval").unwrap().clone().into_i64().unwrap(),
-                            source_ip: config.config.get("source_ip").unwrap().clone().into_string().unwrap(),
+                            scheduler: Mutex::new(Scheduler::new(config)),
+                            mtu_interval: get_required_duration(&config.config, "interval"),
+                            source_ip: get_strings(&config.config, "source_ip"),
+                            bind_interface: config.config.get("bind_interface").map(|s| s.clone().into_string().unwrap()),
                             probes: Mutex::new(Vec::<Probe>::new()),
                             precision: config.config.get("precision")
                                 .unwrap_or(&Yaml::Integer(1))
@@ -68,99 +75,100 @@ impl IcmpMtuChecker {
     }
 }
 
-pub fn icmp_mtu_sender(checker: &Arc<IcmpMtuChecker>) {
+pub fn icmp_mtu_sender(checker: &Arc<IcmpMtuChecker>, sender: Sender<CheckResult>) {
     let (mut icmpv4_tx, _) = transport_channel(4096, Layer3(IpNextHeaderProtocols::Icmp)).unwrap();
-    let mut host = checker.host.clone();
-    host.push_str(":0");
-    let mut source = checker.source_ip.clone();
-    source.push_str(":0");
-    let addr = match host.to_socket_addrs()
-        .unwrap()
-        .next()
-        .unwrap()
-        .ip() {
-            IpAddr::V4(ip) => ip,
-            IpAddr::V6(_) => {
-                return;
-            }
-        };
-    let saddr = match source.to_socket_addrs()
-        .unwrap()
-        .next()
-        .unwrap()
-        .ip() {
-            IpAddr::V4(ip) => ip,
-            IpAddr::V6(_) => {
-                return;
-            }
-        };
+    if let Some(interface) = &checker.bind_interface {
+        if let Err(e) = bind_to_device(icmpv4_tx.socket.fd, interface) {
+            error!("Failed to bind {} to {}: {}", checker.name, interface, e);
+            return;
+        }
+    }
+    let sources = match source_ipv4s(&checker.source_ip) {
+        Ok(s) => s,
+        Err(source_ip) => {
+            error!("Invalid source_ip {} for {}", source_ip, checker.name);
+            return;
+        }
+    };
     let minimum_icmp_size = echo_request::MutableEchoRequestPacket::minimum_packet_size();
     let minimum_ip_size = ipv4::MutableIpv4Packet::minimum_packet_size() + minimum_icmp_size;
     loop {
+        checker.scheduler.lock().unwrap().wait();
+        let targets = {
+            let mut resolver = checker.resolver.lock().unwrap();
+            resolver.resolve_or_report(&checker.name, &checker.labels, &sender);
+            probe_targets(&checker.name, &resolver, &checker.labels, &sources, &checker.bind_interface, &sender)
+        };
         for mtu in checker.mtu.clone() {
-            let payload = vec![0;mtu as usize];
-            let mut ip_packet = vec![0; minimum_ip_size + payload.len()];
-            let mut icmp_packet = vec![0; minimum_icmp_size + payload.len()];
-            let id = random::<u16>();
-            let seq: u16 = 0;
-            let mut icmp = echo_request::MutableEchoRequestPacket::new(&mut icmp_packet[..]).unwrap();
-            icmp.set_identifier(id);
-            icmp.set_sequence_number(seq);
-            icmp.set_icmp_type(IcmpTypes::EchoRequest);
-            icmp.set_payload(&payload[..]);
-            icmp.set_checksum(checksum(&icmp.packet(), 1));
-            let mut ip = ipv4::MutableIpv4Packet::new(&mut ip_packet[..]).unwrap();
-            ip.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
-            ip.set_ttl(255);
-            ip.set_total_length((minimum_ip_size + payload.len()) as u16);
-            ip.set_header_length(5);
-            ip.set_version(4);
-            ip.set_flags(2);
-            ip.set_payload(&mut icmp_packet[..]);
-            ip.set_destination(addr);
-            ip.set_source(saddr);
-            ip.set_checksum(checksum(&ip.packet(), 1));
-            match icmpv4_tx.send_to(ip, IpAddr::V4(addr)) {
-                Ok(_) => {
-                    checker.probes.lock().unwrap().push(Probe{identifier: id, seq: seq, mtu: mtu, sent: Instant::now()});
-                },
-                Err(e) => {
-                    error!("Error sending {:?}", e);
+            for (addr, saddr, labels) in targets.clone() {
+                let payload = vec![0;mtu as usize];
+                let mut ip_packet = vec![0; minimum_ip_size + payload.len()];
+                let mut icmp_packet = vec![0; minimum_icmp_size + payload.len()];
+                let id = random::<u16>();
+                let seq: u16 = 0;
+                let mut icmp = echo_request::MutableEchoRequestPacket::new(&mut icmp_packet[..]).unwrap();
+                icmp.set_identifier(id);
+                icmp.set_sequence_number(seq);
+                icmp.set_icmp_type(IcmpTypes::EchoRequest);
+                icmp.set_payload(&payload[..]);
+                icmp.set_checksum(checksum(&icmp.packet(), 1));
+                let mut ip = ipv4::MutableIpv4Packet::new(&mut ip_packet[..]).unwrap();
+                ip.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
+                ip.set_ttl(255);
+                ip.set_total_length((minimum_ip_size + payload.len()) as u16);
+                ip.set_header_length(5);
+                ip.set_version(4);
+                ip.set_flags(2);
+                ip.set_payload(&mut icmp_packet[..]);
+                ip.set_destination(addr);
+                ip.set_source(saddr);
+                ip.set_checksum(checksum(&ip.packet(), 1));
+                match icmpv4_tx.send_to(ip, IpAddr::V4(addr)) {
+                    Ok(_) => {
+                        checker.probes.lock().unwrap().push(Probe{
+                            identifier: id,
+                            seq: seq,
+                            mtu: mtu,
+                            addr: IpAddr::V4(addr),
+                            labels,
+                            sent: Instant::now()});
+                    },
+                    Err(e) => {
+                        error!("Error sending {:?}", e);
+                    }
                 }
             }
-            thread::sleep(Duration::from_secs(checker.mtu_interval as u64));
+            thread::sleep(checker.mtu_interval);
         }
-        thread::sleep(Duration::from_secs(checker.interval as u64));
     }
 }
 
 pub fn icmp_mtu_receiver(checker: &Arc<IcmpMtuChecker>, sender: Sender<CheckResult>) {
     let (_, mut icmpv4_rx) = transport_channel(4096, Layer4(Ipv4(IpNextHeaderProtocols::Icmp))).unwrap();
-    let mut host = checker.host.clone();
+    if let Some(interface) = &checker.bind_interface {
+        if let Err(e) = bind_to_device(icmpv4_rx.socket.fd, interface) {
+            error!("Failed to bind {} to {}: {}", checker.name, interface, e);
+            return;
+        }
+    }
     let timeout = Duration::new(1, 0);
-    host.push_str(":0");
-    let addr = host.to_socket_addrs()
-        .unwrap()
-        .next()
-        .unwrap()
-        .ip();
     let mut iter = icmp_packet_iter(&mut icmpv4_rx);
     loop {
         match iter.next_with_timeout(timeout) {
             Ok(result) => match result {
                 Some((packet, raddr)) => match EchoReplyPacket::new(packet.packet()) {
                     Some(echo_reply) => {
-                        if packet.get_icmp_type() == IcmpType::new(0) && raddr == addr {
+                        if packet.get_icmp_type() == IcmpType::new(0) {
                             let now = Instant::now();
                             let mut probes = checker.probes.lock().unwrap();
                             for probe in 0..probes.len() {
-                                if probes[probe].identifier == echo_reply.get_identifier() && probes[probe].seq == echo_reply.get_sequence_number() {
+                                if probes[probe].addr == raddr && probes[probe].identifier == echo_reply.get_identifier() && probes[probe].seq == echo_reply.get_sequence_number() {
                                     let finished_probe = probes.swap_remove(probe);
                                     let mut to_emit = CheckResult{
                                         name: checker.name.clone(),
                                         values: HashMap::new(),
                                         processes: Vec::new(),
-                                        labels: checker.labels.clone()};
+                                        labels: finished_probe.labels.clone()};
                                     to_emit.labels.insert(String::from("mtu"), format!("{}", finished_probe.mtu));
                                     to_emit.values.insert(
                                         String::from("rtt"),
@@ -170,7 +178,7 @@ pub fn icmp_mtu_receiver(checker: &Arc<IcmpMtuChecker>, sender: Sender<CheckResu
                                         name: checker.name.clone(),
                                         values: HashMap::new(),
                                         processes: Vec::new(),
-                                        labels: checker.labels.clone()};
+                                        labels: finished_probe.labels};
                                     to_emit.labels.insert(String::from("mtu"), format!("{}", finished_probe.mtu));
                                     to_emit.values.insert(String::from("loss"), 0.0);
                                     sender.send(to_emit).unwrap();
@@ -195,15 +203,15 @@ pub fn icmp_mtu_receiver(checker: &Arc<IcmpMtuChecker>, sender: Sender<CheckResu
             let mut probes = checker.probes.lock().unwrap();
             for probe in (0..probes.len()).rev() {
                 if now.duration_since(probes[probe].sent) > timeout {
+                    let finished_probe = probes.swap_remove(probe);
                     let mut to_emit = CheckResult{
                         name: checker.name.clone(),
                         values: HashMap::new(),
                         processes: Vec::new(),
-                        labels: checker.labels.clone()};
-                    to_emit.labels.insert(String::from("mtu"), format!("{}", probes[probe].mtu));
+                        labels: finished_probe.labels};
+                    to_emit.labels.insert(String::from("mtu"), format!("{}", finished_probe.mtu));
                     to_emit.values.insert(String::from("loss"), 1.0);
                     sender.send(to_emit).unwrap();
-                    probes.swap_remove(probe);
                 }
             }
         }
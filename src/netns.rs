@@ -0,0 +1,38 @@
+use crate::config::ProbeConfig;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use log::error;
+
+// Move current thread to network namespace, by name from /run/netns or by path
+pub fn enter(netns: &str) -> io::Result<()> {
+    let path = if netns.contains('/') {
+        String::from(netns)
+    } else {
+        format!("/run/netns/{}", netns)
+    };
+    let file = File::open(path)?;
+    if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Spawn probe thread in the probe netns. Threads and processes started by the probe inherit it
+pub fn spawn<F>(config: &ProbeConfig, f: F) -> thread::JoinHandle<()>
+where
+    F: FnOnce() + Send + 'static
+{
+    let netns = config.config.get("netns").map(|n| n.clone().into_string().unwrap());
+    let name = config.name.clone();
+    thread::spawn(move || {
+        if let Some(netns) = netns {
+            if let Err(e) = enter(&netns) {
+                error!("Failed to enter netns {} for {}: {}", netns, name, e);
+                return;
+            }
+        }
+        f()
+    })
+}
@@ -0,0 +1,114 @@
+use crate::config::ProbeConfig;
+use crate::scheduler::Scheduler;
+use crate::rate::RateTracker;
+use std::sync::mpsc::Sender;
+use std::fs;
+use std::time::Instant;
+use crate::checker::CheckResult;
+use std::collections::HashMap;
+use log::{debug, error};
+
+const DEFAULT_COUNTERS: [&str; 10] = [
+    "Tcp.RetransSegs", "Tcp.InErrs", "Tcp.OutRsts", "Tcp.AttemptFails", "TcpExt.TCPTimeouts",
+    "TcpExt.ListenDrops", "Udp.InErrors", "Udp.RcvbufErrors", "Icmp.InErrors", "Icmp.InDestUnreachs"];
+// Counters which are current values, not totals. Emitted as is
+const GAUGES: [&str; 7] = [
+    "Ip.Forwarding", "Ip.DefaultTTL", "Tcp.RtoAlgorithm", "Tcp.RtoMin", "Tcp.RtoMax", "Tcp.MaxConn", "Tcp.CurrEstab"];
+
+pub struct NetstatChecker {
+    scheduler: Scheduler,
+    counters: Vec<(String, String)>,
+    name: String,
+    labels: HashMap<String, String>
+}
+
+impl NetstatChecker {
+    pub fn new(config: &ProbeConfig) -> Self {
+        let counters: Vec<String> = match config.config.get("counters") {
+            Some(yaml_rust::Yaml::Array(ref counters)) => counters.iter()
+                .map(|c| c.clone().into_string().unwrap())
+                .collect(),
+            _ => DEFAULT_COUNTERS.iter().map(|c| String::from(*c)).collect()
+        };
+        let mut result = Self{
+            name: config.name.clone(),
+            scheduler: Scheduler::new(config),
+            counters: Vec::new(),
+            labels: config.labels.clone()
+        };
+        for counter in counters {
+            match counter.split_once('.') {
+                Some((protocol, name)) => result.counters.push((String::from(protocol), String::from(name))),
+                None => error!("Counter {} should be <protocol>.<name>", counter)
+            }
+        }
+        result
+    }
+
+    fn emit(&self, sender: &Sender<CheckResult>, protocol: &str, value_name: &str, value: f32) {
+        let mut to_emit = CheckResult{
+            name: self.name.clone(),
+            values: HashMap::new(),
+            processes: Vec::new(),
+            labels: self.labels.clone()};
+        to_emit.labels.insert(String::from("protocol"), String::from(protocol));
+        to_emit.values.insert(String::from(value_name), value);
+        sender.send(to_emit).unwrap();
+    }
+}
+
+// Parse pairs of "<protocol>: <names>" and "<protocol>: <values>" lines
+fn parse_counters(text: &str, counters: &mut HashMap<(String, String), i64>) {
+    let mut lines = text.lines();
+    while let (Some(names), Some(values)) = (lines.next(), lines.next()) {
+        let (protocol, names) = match names.split_once(':') {
+            Some(n) => n,
+            None => continue
+        };
+        let values = match values.split_once(':') {
+            Some((_, v)) => v,
+            None => continue
+        };
+        for (name, value) in names.split_whitespace().zip(values.split_whitespace()) {
+            if let Ok(value) = value.parse::<i64>() {
+                counters.insert((String::from(protocol), String::from(name)), value);
+            }
+        }
+    }
+}
+
+// Read through thread-self, so probes in a netns see their own counters
+fn read_counters() -> HashMap<(String, String), i64> {
+    let mut counters = HashMap::new();
+    for file in ["snmp", "netstat"] {
+        match fs::read_to_string(format!("/proc/thread-self/net/{}", file)) {
+            Ok(text) => parse_counters(&text, &mut counters),
+            Err(e) => error!("Failed to read /proc/net/{}: {}", file, e)
+        }
+    }
+    counters
+}
+
+pub fn netstat(mut checker: NetstatChecker, sender: Sender<CheckResult>) {
+    let mut rates = RateTracker::default();
+    loop {
+        checker.scheduler.wait();
+        let values = read_counters();
+        let now = Instant::now();
+        for (protocol, name) in &checker.counters {
+            let value = match values.get(&(protocol.clone(), name.clone())) {
+                Some(v) => *v,
+                None => {
+                    debug!("Counter {}.{} not found", protocol, name);
+                    continue;
+                }
+            };
+            let counter = format!("{}.{}", protocol, name);
+            if GAUGES.contains(&counter.as_str()) {
+                checker.emit(&sender, protocol, name, value as f32);
+            } else if let Some(rate) = rates.update(&counter, value as u64, now) {
+                checker.emit(&sender, protocol, name, rate);
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use crate::config::{ProbeConfig, get_duration};
+use crate::resolver::Resolver;
+use crate::scheduler::Scheduler;
+use crate::interface::source_addr;
+use yaml_rust::Yaml;
+use std::sync::mpsc::Sender;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::checker::CheckResult;
+use std::collections::HashMap;
+use log::{debug, error};
+
+const NTP_PACKET_SIZE: usize = 48;
+// Seconds between 1900-01-01 (NTP era 0) and 1970-01-01
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+pub struct NtpChecker {
+    resolver: Resolver,
+    scheduler: Scheduler,
+    timeout: Duration,
+    name: String,
+    source: SocketAddr,
+    precision: i64,
+    labels: HashMap<String, String>
+}
+
+struct NtpReply {
+    stratum: u8,
+    root_dispersion: f64,
+    refid: String,
+    originate: u64,
+    receive: u64,
+    transmit: u64
+}
+
+impl NtpChecker {
+    pub fn new(config: &ProbeConfig) -> Self {
+        let source = config.config.get("source")
+            .unwrap_or(&Yaml::String(String::from("0.0.0.0:0")))
+            .clone()
+            .into_string()
+            .unwrap_or_default();
+        let source = match source_addr(&source) {
+            Some(addr) => addr,
+            None => {
+                error!("Invalid source {} of {}", source, config.name);
+                process::exit(1);
+            }
+        };
+        Self{
+            name: config.name.clone(),
+            resolver: Resolver::new(config, config.host.clone()),
+            scheduler: Scheduler::new(config),
+            timeout: get_duration(&config.config, "timeout", Duration::from_secs(1)),
+            source,
+            precision: config.config.get("precision")
+                .unwrap_or(&Yaml::Integer(1))
+                .clone()
+                .into_i64()
+                .unwrap(),
+            labels: config.labels.clone()
+        }
+    }
+
+    fn emit(&self, sender: &Sender<CheckResult>, labels: &HashMap<String, String>, value_name: &str, value: f32) {
+        let mut to_emit = CheckResult{
+            name: self.name.clone(),
+            values: HashMap::new(),
+            processes: Vec::new(),
+            labels: labels.clone()};
+        to_emit.values.insert(String::from(value_name), value);
+        sender.send(to_emit).unwrap();
+    }
+}
+
+fn to_ntp_timestamp(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap();
+    let seconds = since_epoch.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = ((since_epoch.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (seconds << 32) | fraction
+}
+
+// Difference a - b of two NTP timestamps in microseconds
+fn ntp_diff_micros(a: u64, b: u64) -> f64 {
+    (a.wrapping_sub(b) as i64) as f64 * 1_000_000.0 / 4_294_967_296.0
+}
+
+// Round trip time without the server processing time and clock offset, in microseconds
+fn rtt_offset(sent: u64, reply: &NtpReply, received: u64) -> (f64, f64) {
+    let rtt = ntp_diff_micros(received, sent) - ntp_diff_micros(reply.transmit, reply.receive);
+    let offset = (ntp_diff_micros(reply.receive, sent) + ntp_diff_micros(reply.transmit, received)) / 2.0;
+    (rtt, offset)
+}
+
+fn parse_reply(buffer: &[u8]) -> Option<NtpReply> {
+    if buffer.len() < NTP_PACKET_SIZE {
+        return None;
+    }
+    // Server (4) or broadcast (5) mode only
+    let mode = buffer[0] & 0x7;
+    if mode != 4 && mode != 5 {
+        return None;
+    }
+    let stratum = buffer[1];
+    let dispersion = u32::from_be_bytes([buffer[8], buffer[9], buffer[10], buffer[11]]);
+    let refid = if stratum <= 1 {
+        buffer[12..16].iter()
+            .filter(|c| c.is_ascii_graphic())
+            .map(|c| *c as char)
+            .collect()
+    } else {
+        Ipv4Addr::new(buffer[12], buffer[13], buffer[14], buffer[15]).to_string()
+    };
+    Some(NtpReply{
+        stratum,
+        root_dispersion: dispersion as f64 * 1_000_000.0 / 65536.0,
+        refid,
+        originate: u64::from_be_bytes(buffer[24..32].try_into().unwrap()),
+        receive: u64::from_be_bytes(buffer[32..40].try_into().unwrap()),
+        transmit: u64::from_be_bytes(buffer[40..48].try_into().unwrap())
+    })
+}
+
+pub fn ntp(mut checker: NtpChecker, sender: Sender<CheckResult>) {
+    let socket = match UdpSocket::bind(checker.source) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Failed to bind {} of {}: {}", checker.source, checker.name, e);
+            process::exit(1);
+        }
+    };
+    socket.set_write_timeout(Some(checker.timeout)).unwrap();
+    socket.set_read_timeout(Some(checker.timeout)).unwrap();
+    let precision = checker.precision as f64;
+    let mut buffer = [0; 1024];
+    loop {
+        checker.scheduler.wait();
+        checker.resolver.resolve_or_report(&checker.name, &checker.labels, &sender);
+        for addr in checker.resolver.addresses() {
+            let labels = checker.resolver.labels(&checker.labels, &addr);
+            let mut request = [0u8; NTP_PACKET_SIZE];
+            // LI = 0, VN = 4, Mode = 3 (client)
+            request[0] = 0x23;
+            let sent = to_ntp_timestamp(SystemTime::now());
+            request[40..48].copy_from_slice(&sent.to_be_bytes());
+            if socket.connect(addr).is_err() || socket.send(&request).is_err() {
+                debug!("Failed to send ntp request");
+                checker.emit(&sender, &labels, "loss", 1.0);
+                continue;
+            }
+            let mut reply = None;
+            // Drop late replies to previous requests
+            while let Ok(size) = socket.recv(&mut buffer) {
+                let received = to_ntp_timestamp(SystemTime::now());
+                match parse_reply(&buffer[..size]) {
+                    Some(r) if r.originate == sent => {
+                        reply = Some((r, received));
+                        break;
+                    },
+                    _ => {
+                        debug!("Unexpected ntp packet");
+                    }
+                }
+            }
+            match reply {
+                Some((r, received)) if r.stratum != 0 => {
+                    let mut labels = labels.clone();
+                    labels.insert(String::from("refid"), r.refid.clone());
+                    let (rtt, offset) = rtt_offset(sent, &r, received);
+                    checker.emit(&sender, &labels, "rtt", (rtt / precision) as f32);
+                    checker.emit(&sender, &labels, "offset", (offset / precision) as f32);
+                    checker.emit(&sender, &labels, "stratum", r.stratum as f32);
+                    checker.emit(&sender, &labels, "root_dispersion", (r.root_dispersion / precision) as f32);
+                    checker.emit(&sender, &labels, "loss", 0.0);
+                },
+                Some((r, _)) => {
+                    debug!("Kiss-o'-death from ntp server: {}", r.refid);
+                    checker.emit(&sender, &labels, "loss", 1.0);
+                },
+                None => {
+                    checker.emit(&sender, &labels, "loss", 1.0);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-01-01T00:00:00Z
+    const BASE: u64 = (3_913_056_000 << 32) | (1 << 31);
+
+    fn packet(mode: u8, stratum: u8, refid: [u8; 4]) -> Vec<u8> {
+        let mut buffer = vec![0u8; NTP_PACKET_SIZE];
+        buffer[0] = 0x20 | mode;
+        buffer[1] = stratum;
+        // 1.5 seconds root dispersion
+        buffer[8..12].copy_from_slice(&0x0001_8000u32.to_be_bytes());
+        buffer[12..16].copy_from_slice(&refid);
+        buffer[24..32].copy_from_slice(&BASE.to_be_bytes());
+        buffer[32..40].copy_from_slice(&(BASE + (1 << 30)).to_be_bytes());
+        buffer[40..48].copy_from_slice(&(BASE + (1 << 31)).to_be_bytes());
+        buffer
+    }
+
+    #[test]
+    fn timestamp() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_704_067_200_500);
+        assert_eq!(to_ntp_timestamp(time), BASE);
+    }
+
+    #[test]
+    fn diff() {
+        assert_eq!(ntp_diff_micros(BASE + (1 << 32), BASE), 1_000_000.0);
+        assert_eq!(ntp_diff_micros(BASE, BASE + (1 << 30)), -250_000.0);
+        // Era rollover
+        assert_eq!(ntp_diff_micros(1 << 31, u64::MAX - (1 << 31) + 1), 1_000_000.0);
+    }
+
+    #[test]
+    fn server_reply() {
+        let reply = parse_reply(&packet(4, 2, [192, 0, 2, 1])).unwrap();
+        assert_eq!(reply.stratum, 2);
+        assert_eq!(reply.refid, "192.0.2.1");
+        assert_eq!(reply.root_dispersion, 1_500_000.0);
+        assert_eq!(reply.originate, BASE);
+        assert_eq!(reply.receive, BASE + (1 << 30));
+        assert_eq!(reply.transmit, BASE + (1 << 31));
+        // Sent at BASE, server received 250ms later and replied 250ms after that, received at BASE + 1s
+        let (rtt, offset) = rtt_offset(BASE, &reply, BASE + (1 << 32));
+        assert_eq!(rtt, 750_000.0);
+        assert_eq!(offset, -125_000.0);
+    }
+
+    #[test]
+    fn primary_and_kiss_refid() {
+        assert_eq!(parse_reply(&packet(4, 1, *b"GPS\0")).unwrap().refid, "GPS");
+        let kiss = parse_reply(&packet(4, 0, *b"RATE")).unwrap();
+        assert_eq!(kiss.stratum, 0);
+        assert_eq!(kiss.refid, "RATE");
+        assert_eq!(parse_reply(&packet(5, 1, *b"PPS\0")).unwrap().refid, "PPS");
+    }
+
+    #[test]
+    fn invalid_reply() {
+        assert!(parse_reply(&packet(3, 2, [0; 4])).is_none());
+        assert!(parse_reply(&packet(4, 2, [0; 4])[..NTP_PACKET_SIZE - 1]).is_none());
+        assert!(parse_reply(&[]).is_none());
+    }
+}
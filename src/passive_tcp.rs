@@ -0,0 +1,286 @@
+extern crate pnet;
+
+use log::{debug, error};
+use crate::config::{ProbeConfig, get_duration};
+use yaml_rust::Yaml;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
+use std::sync::mpsc::Sender;
+use pnet::datalink::{self, Channel};
+use pnet::ipnetwork::Ipv4Network;
+use pnet::packet::Packet;
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::tcp::{TcpFlags, TcpPacket};
+use std::collections::HashMap;
+use std::process;
+use crate::checker::CheckResult;
+
+const MAX_PENDING_SEGMENTS: usize = 64;
+
+pub struct PassiveTcpChecker {
+    interface: String,
+    interval: Duration,
+    flow_timeout: Duration,
+    prefix_len: u8,
+    aggregate: Vec<String>,
+    name: String,
+    precision: i64,
+    labels: HashMap<String, String>
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct FlowKey {
+    local_ip: Ipv4Addr,
+    local_port: u16,
+    remote_ip: Ipv4Addr,
+    remote_port: u16
+}
+
+struct Flow {
+    // Outgoing SYN or SYN-ACK waiting for the handshake to complete
+    handshake: Option<(u32, Instant)>,
+    // Outgoing data segments waiting for ACK: end sequence and time sent
+    pending: Vec<(u32, Instant)>,
+    highest_sent: Option<u32>,
+    last_seen: Instant
+}
+
+impl Flow {
+    fn new(now: Instant) -> Self {
+        Self{handshake: None, pending: Vec::new(), highest_sent: None, last_seen: now}
+    }
+}
+
+// Sequence number comparison with wrap around
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+impl PassiveTcpChecker {
+    pub fn new(config: &ProbeConfig) -> Self {
+        let prefix_len = config.config.get("prefix_len")
+            .unwrap_or(&Yaml::Integer(24))
+            .clone()
+            .into_i64()
+            .unwrap();
+        if !(0..=32).contains(&prefix_len) {
+            error!("Invalid prefix_len {} for {}", prefix_len, config.name);
+            process::exit(1);
+        }
+        let mut result = Self{
+            name: config.name.clone(),
+            interface: match config.config.get("interface").and_then(|i| i.as_str()) {
+                Some(interface) => String::from(interface),
+                None => {
+                    error!("Interface is required for {}", config.name);
+                    process::exit(1);
+                }
+            },
+            interval: config.interval,
+            flow_timeout: get_duration(&config.config, "flow_timeout", Duration::from_secs(60)),
+            prefix_len: prefix_len as u8,
+            aggregate: Vec::new(),
+            precision: config.config.get("precision")
+                .unwrap_or(&Yaml::Integer(1))
+                .clone()
+                .into_i64()
+                .unwrap(),
+            labels: config.labels.clone()
+        };
+        match config.config.get("aggregate") {
+            Some(yaml_rust::Yaml::Array(ref a)) => {
+                for key in a {
+                    result.aggregate.push(key.clone().into_string().unwrap());
+                }
+            },
+            _ => result.aggregate.push(String::from("remote_subnet"))
+        }
+        result
+    }
+
+    fn flow_labels(&self, flow: &FlowKey) -> HashMap<String, String> {
+        let mut labels = self.labels.clone();
+        for key in &self.aggregate {
+            let value = match key.as_str() {
+                "remote_subnet" => {
+                    let network = Ipv4Network::new(flow.remote_ip, self.prefix_len).unwrap();
+                    format!("{}_{}", network.network(), network.prefix())
+                },
+                "remote_ip" => flow.remote_ip.to_string(),
+                "remote_port" => flow.remote_port.to_string(),
+                "local_port" => flow.local_port.to_string(),
+                _ => continue
+            };
+            labels.insert(key.clone(), value);
+        }
+        labels
+    }
+
+    fn emit(&self, sender: &Sender<CheckResult>, labels: HashMap<String, String>, value_name: &str, value: f32) {
+        let mut to_emit = CheckResult{
+            name: self.name.clone(),
+            values: HashMap::new(),
+            processes: Vec::new(),
+            labels};
+        to_emit.values.insert(String::from(value_name), value);
+        sender.send(to_emit).unwrap();
+    }
+
+    fn emit_rtt(&self, sender: &Sender<CheckResult>, key: &FlowKey, sent: Instant, now: Instant) {
+        self.emit(
+            sender,
+            self.flow_labels(key),
+            "rtt",
+            (now.duration_since(sent).as_micros() as f32) / self.precision as f32);
+    }
+}
+
+pub fn passive_tcp(checker: PassiveTcpChecker, sender: Sender<CheckResult>) {
+    let interface = match datalink::interfaces().into_iter().find(|i| i.name == checker.interface) {
+        Some(i) => i,
+        None => {
+            error!("Interface {} not found", checker.interface);
+            return;
+        }
+    };
+    let local_ips: Vec<Ipv4Addr> = interface.ips.iter()
+        .filter_map(|ip| match ip.ip() {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None
+        })
+        .collect();
+    let config = datalink::Config {
+        read_timeout: Some(Duration::from_millis(100)),
+        promiscuous: false,
+        ..Default::default()
+    };
+    let mut rx = match datalink::channel(&interface, config) {
+        Ok(Channel::Ethernet(_, rx)) => rx,
+        Ok(_) => {
+            error!("Unsupported channel type on {}", checker.interface);
+            return;
+        },
+        Err(e) => {
+            error!("Failed to open {}: {}", checker.interface, e);
+            return;
+        }
+    };
+    let interval = checker.interval;
+    let flow_timeout = checker.flow_timeout;
+    let mut flows: HashMap<FlowKey, Flow> = HashMap::new();
+    let mut retransmits: HashMap<FlowKey, u32> = HashMap::new();
+    let mut next_emit = Instant::now() + interval;
+    loop {
+        // Checked before reading, frames skipped below must not delay emission
+        let now = Instant::now();
+        if now >= next_emit {
+            next_emit = now + interval;
+            let mut aggregated: HashMap<Vec<(String, String)>, u32> = HashMap::new();
+            for key in flows.keys() {
+                retransmits.entry(*key).or_insert(0);
+            }
+            for (key, count) in retransmits.drain() {
+                let mut labels: Vec<(String, String)> = checker.flow_labels(&key).into_iter().collect();
+                labels.sort();
+                *aggregated.entry(labels).or_insert(0) += count;
+            }
+            for (labels, count) in aggregated {
+                checker.emit(&sender, labels.into_iter().collect(), "retransmits", count as f32);
+            }
+            flows.retain(|_, flow| now.duration_since(flow.last_seen) < flow_timeout);
+        }
+        match rx.next() {
+            Ok(frame) => {
+                let now = Instant::now();
+                let ethernet = match EthernetPacket::new(frame) {
+                    Some(e) if e.get_ethertype() == EtherTypes::Ipv4 => e,
+                    _ => continue
+                };
+                let ip = match Ipv4Packet::new(ethernet.payload()) {
+                    Some(ip) if ip.get_next_level_protocol() == IpNextHeaderProtocols::Tcp => ip,
+                    _ => continue
+                };
+                let tcp = match TcpPacket::new(ip.payload()) {
+                    Some(tcp) => tcp,
+                    None => continue
+                };
+                let outgoing = local_ips.contains(&ip.get_source());
+                let key = if outgoing {
+                    FlowKey{
+                        local_ip: ip.get_source(),
+                        local_port: tcp.get_source(),
+                        remote_ip: ip.get_destination(),
+                        remote_port: tcp.get_destination()}
+                } else if local_ips.contains(&ip.get_destination()) {
+                    FlowKey{
+                        local_ip: ip.get_destination(),
+                        local_port: tcp.get_destination(),
+                        remote_ip: ip.get_source(),
+                        remote_port: tcp.get_source()}
+                } else {
+                    continue
+                };
+                let flags = tcp.get_flags();
+                if flags & (TcpFlags::FIN | TcpFlags::RST) != 0 {
+                    flows.remove(&key);
+                    continue;
+                }
+                let header_len = tcp.get_data_offset() as usize * 4;
+                let ip_payload_len = (ip.get_total_length() as usize).saturating_sub(ip.get_header_length() as usize * 4);
+                let payload_len = ip_payload_len.saturating_sub(header_len) as u32;
+                let flow = flows.entry(key).or_insert_with(|| Flow::new(now));
+                flow.last_seen = now;
+                if outgoing {
+                    if flags & TcpFlags::SYN != 0 {
+                        // Our SYN (client) or SYN-ACK (server) starts handshake timing
+                        flow.handshake = Some((tcp.get_sequence().wrapping_add(1), now));
+                    } else if payload_len > 0 {
+                        let end = tcp.get_sequence().wrapping_add(payload_len);
+                        match flow.highest_sent {
+                            Some(highest) if seq_le(end, highest) => {
+                                *retransmits.entry(key).or_insert(0) += 1;
+                                // Karn's algorithm: no samples from retransmitted data
+                                flow.pending.retain(|(seq, _)| !seq_le(*seq, end));
+                            },
+                            _ => {
+                                flow.highest_sent = Some(end);
+                                if flow.pending.len() < MAX_PENDING_SEGMENTS {
+                                    flow.pending.push((end, now));
+                                }
+                            }
+                        }
+                    }
+                } else if flags & TcpFlags::ACK != 0 {
+                    let ack = tcp.get_acknowledgement();
+                    if let Some((seq, sent)) = flow.handshake {
+                        if seq == ack {
+                            flow.handshake = None;
+                            checker.emit_rtt(&sender, &key, sent, now);
+                            continue;
+                        }
+                    }
+                    // Sample the newest segment covered by this ACK
+                    let mut sample = None;
+                    flow.pending.retain(|(seq, sent)| {
+                        if seq_le(*seq, ack) {
+                            sample = Some(*sent);
+                            false
+                        } else {
+                            true
+                        }
+                    });
+                    if let Some(sent) = sample {
+                        checker.emit_rtt(&sender, &key, sent, now);
+                    }
+                }
+            },
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::TimedOut {
+                    debug!("Error getting packet {:?}", e);
+                }
+            }
+        }
+    }
+}
@@ -1,19 +1,23 @@
 extern crate pnet;
 
-use log::debug;
-use crate::config::ProbeConfig;
+use log::{debug, error};
+use crate::config::{ProbeConfig, get_strings};
+use crate::resolver::Resolver;
+use crate::scheduler::Scheduler;
+use crate::interface::{bind_to_device, probe_targets, source_ipv4s};
+use crate::timestamp;
+use crate::encap::Encap;
 use std::sync::{Arc, Mutex};
-use std::net::{IpAddr, ToSocketAddrs};
-use std::thread;
+use std::net::IpAddr;
 use std::time::{Duration, Instant};
 use std::sync::mpsc::Sender;
 use rand::random;
-use pnet::transport::{transport_channel, icmp_packet_iter};
+use pnet::transport::transport_channel;
 use pnet::transport::TransportChannelType::{Layer3, Layer4};
 use pnet::transport::TransportProtocol::Ipv4;
 use pnet::packet::{ipv4, Packet};
 use pnet::packet::ip::IpNextHeaderProtocols;
-use pnet::packet::icmp::{IcmpType, IcmpTypes, echo_request};
+use pnet::packet::icmp::{IcmpTypes, echo_request};
 use pnet::packet::icmp::echo_reply::EchoReplyPacket;
 use pnet::util::checksum;
 use std::collections::HashMap;
@@ -21,10 +25,12 @@ use crate::checker::CheckResult;
 use yaml_rust::Yaml;
 
 pub struct IcmpChecker {
-    host: String,
+    resolver: Mutex<Resolver>,
     mtu: i64,
-    interval: i64,
-    source_ip: String,
+    scheduler: Mutex<Scheduler>,
+    source_ip: Vec<String>,
+    bind_interface: Option<String>,
+    encap: Option<Encap>,
     name: String,
     probes: Mutex<Vec<Probe>>,
     precision: i64,
@@ -34,17 +40,23 @@ pub struct IcmpChecker {
 struct Probe {
     identifier: u16,
     seq: u16,
-    sent: Instant
+    addr: IpAddr,
+    labels: HashMap<String, String>,
+    sent: Instant,
+    sent_time: Duration,
+    sent_kernel: Option<Duration>
 }
 
 impl IcmpChecker {
     pub fn new(config: &ProbeConfig) -> Self {
         Self{
             name: config.name.clone(),
-            host: config.host.clone(),
+            resolver: Mutex::new(Resolver::new(config, format!("{}:0", config.host))),
             mtu: config.config.get("mtu").unwrap().clone().into_i64().unwrap(),
-            interval: config.interval.clone(),
-            source_ip: config.config.get("source_ip").unwrap().clone().into_string().unwrap(),
+            scheduler: Mutex::new(Scheduler::new(config)),
+            source_ip: get_strings(&config.config, "source_ip"),
+            bind_interface: config.config.get("bind_interface").map(|s| s.clone().into_string().unwrap()),
+            encap: Encap::new(&config.config),
             precision: config.config.get("precision")
                 .unwrap_or(&Yaml::Integer(1))
                 .clone()
@@ -56,105 +68,162 @@ impl IcmpChecker {
     }
 }
 
-pub fn icmp_sender(checker: &Arc<IcmpChecker>) {
+pub fn icmp_sender(checker: &Arc<IcmpChecker>, sender: Sender<CheckResult>) {
     let (mut icmpv4_tx, _) = transport_channel(4096, Layer3(IpNextHeaderProtocols::Icmp)).unwrap();
-    let mut host = checker.host.clone();
-    host.push_str(":0");
-    let mut source = checker.source_ip.clone();
-    source.push_str(":0");
-    let addr = match host.to_socket_addrs()
-        .unwrap()
-        .next()
-        .unwrap()
-        .ip() {
-            IpAddr::V4(ip) => ip,
-            IpAddr::V6(_) => {
-                return;
-            }
-        };
-    let saddr = match source.to_socket_addrs()
-        .unwrap()
-        .next()
-        .unwrap()
-        .ip() {
-            IpAddr::V4(ip) => ip,
-            IpAddr::V6(_) => {
+    if let Some(interface) = &checker.bind_interface {
+        if let Err(e) = bind_to_device(icmpv4_tx.socket.fd, interface) {
+            error!("Failed to bind {} to {}: {}", checker.name, interface, e);
+            return;
+        }
+    }
+    if let Err(e) = timestamp::enable(icmpv4_tx.socket.fd) {
+        debug!("Kernel timestamps are not available: {}", e);
+    }
+    let sources = match source_ipv4s(&checker.source_ip) {
+        Ok(s) => s,
+        Err(source_ip) => {
+            error!("Invalid source_ip {} for {}", source_ip, checker.name);
+            return;
+        }
+    };
+    let tunnel = match &checker.encap {
+        Some(encap) => match encap.tunnel(&checker.bind_interface) {
+            Ok(t) => Some(t),
+            Err(e) => {
+                error!("Failed to set up encapsulation for {}: {}", checker.name, e);
                 return;
             }
-        };
+        },
+        None => None
+    };
     let minimum_icmp_size = echo_request::MutableEchoRequestPacket::minimum_packet_size();
     let minimum_ip_size = ipv4::MutableIpv4Packet::minimum_packet_size() + minimum_icmp_size;
     let payload = vec![0;checker.mtu as usize];
+    let packet_size = minimum_ip_size + payload.len();
     loop {
-        let mut ip_packet = vec![0; minimum_ip_size + payload.len()];
-        let mut icmp_packet = vec![0; minimum_icmp_size + payload.len()];
-        let id = random::<u16>();
-        let seq: u16 = 0;
-        let mut icmp = echo_request::MutableEchoRequestPacket::new(&mut icmp_packet[..]).unwrap();
-        icmp.set_identifier(id);
-        icmp.set_sequence_number(seq);
-        icmp.set_icmp_type(IcmpTypes::EchoRequest);
-        icmp.set_payload(&payload[..]);
-        icmp.set_checksum(checksum(&icmp.packet(), 1));
-        let mut ip = ipv4::MutableIpv4Packet::new(&mut ip_packet[..]).unwrap();
-        ip.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
-        ip.set_ttl(255);
-        ip.set_total_length((minimum_ip_size + payload.len()) as u16);
-        ip.set_header_length(5);
-        ip.set_version(4);
-        ip.set_flags(2);
-        ip.set_payload(&mut icmp_packet[..]);
-        ip.set_destination(addr);
-        ip.set_source(saddr);
-        ip.set_checksum(checksum(&ip.packet(), 1));
-        match icmpv4_tx.send_to(ip, IpAddr::V4(addr)) {
-            Ok(_) => {
-                checker.probes.lock().unwrap().push(Probe{identifier: id, seq: seq, sent: Instant::now()});
-            },
-            Err(e) => {
+        checker.scheduler.lock().unwrap().wait();
+        let mut resolver = checker.resolver.lock().unwrap();
+        resolver.resolve_or_report(&checker.name, &checker.labels, &sender);
+        for (addr, saddr, labels) in probe_targets(&checker.name, &resolver, &checker.labels, &sources, &checker.bind_interface, &sender) {
+            let mut ip_packet = vec![0; minimum_ip_size + payload.len()];
+            let mut icmp_packet = vec![0; minimum_icmp_size + payload.len()];
+            let id = random::<u16>();
+            let seq: u16 = 0;
+            let mut icmp = echo_request::MutableEchoRequestPacket::new(&mut icmp_packet[..]).unwrap();
+            icmp.set_identifier(id);
+            icmp.set_sequence_number(seq);
+            icmp.set_icmp_type(IcmpTypes::EchoRequest);
+            icmp.set_payload(&payload[..]);
+            icmp.set_checksum(checksum(&icmp.packet(), 1));
+            let mut ip = ipv4::MutableIpv4Packet::new(&mut ip_packet[..]).unwrap();
+            ip.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
+            ip.set_ttl(255);
+            ip.set_total_length((minimum_ip_size + payload.len()) as u16);
+            ip.set_header_length(5);
+            ip.set_version(4);
+            ip.set_flags(2);
+            ip.set_payload(&mut icmp_packet[..]);
+            ip.set_destination(addr);
+            ip.set_source(saddr);
+            // Header only, the kernel does not fix it up inside an encapsulated packet
+            ip.set_checksum(ipv4::checksum(&ip.to_immutable()));
+            // Register probe before sending, so a fast reply can't outrun it
+            let mut probes = checker.probes.lock().unwrap();
+            probes.push(Probe{
+                identifier: id,
+                seq: seq,
+                addr: IpAddr::V4(addr),
+                labels,
+                sent: Instant::now(),
+                sent_time: timestamp::now(),
+                sent_kernel: None});
+            let sent = match &tunnel {
+                Some(tunnel) => icmpv4_tx.send_to(
+                    ipv4::Ipv4Packet::new(&tunnel.wrap(ip.packet())).unwrap(),
+                    IpAddr::V4(tunnel.remote())),
+                None => icmpv4_tx.send_to(ip, IpAddr::V4(addr))
+            };
+            if let Err(e) = sent {
                 debug!("Error sending {:?}", e);
+                probes.pop();
+            }
+            // Sent packet is at the end of error queue entry, after link layer header
+            for (packet, sent_kernel) in timestamp::sent(icmpv4_tx.socket.fd) {
+                if packet.len() < packet_size {
+                    continue;
+                }
+                let request = match ipv4::Ipv4Packet::new(&packet[packet.len() - packet_size..]) {
+                    Some(ip) => match echo_request::EchoRequestPacket::new(ip.payload()) {
+                        Some(request) => (request.get_identifier(), request.get_sequence_number()),
+                        None => continue
+                    },
+                    None => continue
+                };
+                for probe in probes.iter_mut() {
+                    if (probe.identifier, probe.seq) == request {
+                        probe.sent_kernel = Some(sent_kernel);
+                    }
+                }
             }
         }
-        thread::sleep(Duration::from_secs(checker.interval as u64));
     }
 }
 
 pub fn icmp_receiver(checker: &Arc<IcmpChecker>, sender: Sender<CheckResult>) {
-    let (_, mut icmpv4_rx) = transport_channel(4096, Layer4(Ipv4(IpNextHeaderProtocols::Icmp))).unwrap();
-    let mut host = checker.host.clone();
+    let (_, icmpv4_rx) = transport_channel(4096, Layer4(Ipv4(IpNextHeaderProtocols::Icmp))).unwrap();
+    if let Some(interface) = &checker.bind_interface {
+        if let Err(e) = bind_to_device(icmpv4_rx.socket.fd, interface) {
+            error!("Failed to bind {} to {}: {}", checker.name, interface, e);
+            return;
+        }
+    }
+    if let Err(e) = timestamp::enable(icmpv4_rx.socket.fd) {
+        debug!("Kernel timestamps are not available: {}", e);
+    }
     let timeout = Duration::new(1, 0);
-    host.push_str(":0");
-    let addr = host.to_socket_addrs()
-        .unwrap()
-        .next()
-        .unwrap()
-        .ip();
-    let mut iter = icmp_packet_iter(&mut icmpv4_rx);
+    let mut buffer = [0u8; 4096];
     loop {
-        match iter.next_with_timeout(timeout) {
-            Ok(result) => match result {
-                Some((packet, raddr)) => match EchoReplyPacket::new(packet.packet()) {
+        match timestamp::recv(icmpv4_rx.socket.fd, &mut buffer, timeout) {
+            Ok(Some((size, received_kernel))) => {
+                let now = Instant::now();
+                let received_time = timestamp::now();
+                let ip = match ipv4::Ipv4Packet::new(&buffer[..size]) {
+                    Some(ip) => ip,
+                    None => continue
+                };
+                let raddr = IpAddr::V4(ip.get_source());
+                match EchoReplyPacket::new(ip.payload()) {
                     Some(echo_reply) => {
-                        if packet.get_icmp_type() == IcmpType::new(0) && raddr == addr {
-                            let now = Instant::now();
+                        if echo_reply.get_icmp_type() == IcmpTypes::EchoReply {
                             let mut probes = checker.probes.lock().unwrap();
                             for probe in 0..probes.len() {
-                                if probes[probe].identifier == echo_reply.get_identifier() && probes[probe].seq == echo_reply.get_sequence_number() {
+                                if probes[probe].addr == raddr && probes[probe].identifier == echo_reply.get_identifier() && probes[probe].seq == echo_reply.get_sequence_number() {
                                     let finished_probe = probes.swap_remove(probe);
+                                    let rtt = received_kernel.unwrap_or(received_time)
+                                        .saturating_sub(finished_probe.sent_kernel.unwrap_or(finished_probe.sent_time));
                                     let mut to_emit = CheckResult{
                                         name: checker.name.clone(),
                                         values: HashMap::new(),
                                         processes: Vec::new(),
-                                        labels: checker.labels.clone()};
+                                        labels: finished_probe.labels.clone()};
                                     to_emit.values.insert(
                                         String::from("rtt"),
+                                        (rtt.as_micros() as f32) / checker.precision as f32);
+                                    sender.send(to_emit).unwrap();
+                                    let mut to_emit = CheckResult{
+                                        name: checker.name.clone(),
+                                        values: HashMap::new(),
+                                        processes: Vec::new(),
+                                        labels: finished_probe.labels.clone()};
+                                    to_emit.values.insert(
+                                        String::from("rtt_user"),
                                         (now.duration_since(finished_probe.sent).as_micros() as f32) / checker.precision as f32);
                                     sender.send(to_emit).unwrap();
                                     let mut to_emit = CheckResult{
                                         name: checker.name.clone(),
                                         values: HashMap::new(),
                                         processes: Vec::new(),
-                                        labels: checker.labels.clone()};
+                                        labels: finished_probe.labels};
                                     to_emit.values.insert(String::from("loss"), 0.0);
                                     sender.send(to_emit).unwrap();
                                     break;
@@ -162,28 +231,27 @@ pub fn icmp_receiver(checker: &Arc<IcmpChecker>, sender: Sender<CheckResult>) {
                             }
                         }
                     },
-                        None => {
-                            debug!("Error getting packet");
+                    None => {
+                        debug!("Error decoding icmp packet");
                     }
-                },
-                None => {
                 }
             },
-            Err(_) => {
-                debug!("Error getting packet");
+            Ok(None) => {},
+            Err(e) => {
+                debug!("Error getting packet {:?}", e);
             }
         }
         {
             let now = Instant::now();
             let mut probes = checker.probes.lock().unwrap();
-            for probe in probes.len()..0 {
-                if now.duration_since(probes[probe-1].sent) > timeout {
-                    probes.swap_remove(probe-1);
+            for probe in (0..probes.len()).rev() {
+                if now.duration_since(probes[probe].sent) > timeout {
+                    let finished_probe = probes.swap_remove(probe);
                     let mut to_emit = CheckResult{
                         name: checker.name.clone(),
                         values: HashMap::new(),
                         processes: Vec::new(),
-                        labels: checker.labels.clone()};
+                        labels: finished_probe.labels};
                     to_emit.values.insert(String::from("loss"), 1.0);
                     sender.send(to_emit).unwrap();
                 }
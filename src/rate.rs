@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+// Per second rates of monotonic counters. Decreasing counter is treated as reset, unless it wraps
+#[derive(Default)]
+pub struct RateTracker {
+    previous: HashMap<String, (u64, Instant)>
+}
+
+impl RateTracker {
+    // Rate since the previous update of the same counter, None on the first update and after reset
+    pub fn update(&mut self, key: &str, value: u64, now: Instant) -> Option<f32> {
+        self.rate(key, value, now, false)
+    }
+
+    // 32 bit counter wrapping around at 2^32, decrease is taken as a single wraparound
+    pub fn update_counter32(&mut self, key: &str, value: u32, now: Instant) -> Option<f32> {
+        self.rate(key, value as u64, now, true)
+    }
+
+    fn rate(&mut self, key: &str, value: u64, now: Instant, wraps: bool) -> Option<f32> {
+        let (previous_value, previous_time) = self.previous.insert(String::from(key), (value, now))?;
+        let elapsed = now.duration_since(previous_time).as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+        let delta = if value >= previous_value {
+            value - previous_value
+        } else if wraps {
+            value + (1 << 32) - previous_value
+        } else {
+            return None;
+        };
+        Some((delta as f64 / elapsed) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn counter32_wraps() {
+        let mut rates = RateTracker::default();
+        let start = Instant::now();
+        assert_eq!(rates.update_counter32("c", u32::MAX - 9, start), None);
+        assert_eq!(rates.update_counter32("c", 10, start + Duration::from_secs(2)), Some(10.0));
+    }
+
+    #[test]
+    fn counter64_decrease_is_reset() {
+        let mut rates = RateTracker::default();
+        let start = Instant::now();
+        assert_eq!(rates.update("c", 100, start), None);
+        assert_eq!(rates.update("c", 10, start + Duration::from_secs(1)), None);
+        assert_eq!(rates.update("c", 30, start + Duration::from_secs(2)), Some(20.0));
+    }
+}
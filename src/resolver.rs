@@ -0,0 +1,81 @@
+use crate::config::{ProbeConfig, get_duration};
+use crate::checker::CheckResult;
+use yaml_rust::Yaml;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use log::error;
+
+pub struct Resolver {
+    host: String,
+    ttl: Duration,
+    all: bool,
+    addrs: Vec<SocketAddr>,
+    resolved: Option<Instant>
+}
+
+impl Resolver {
+    pub fn new(config: &ProbeConfig, host: String) -> Self {
+        Self{
+            host,
+            ttl: get_duration(&config.config, "resolve_ttl", Duration::from_secs(60)),
+            all: config.config.get("resolve_all")
+                .unwrap_or(&Yaml::Boolean(false))
+                .clone()
+                .as_bool()
+                .unwrap(),
+            addrs: Vec::new(),
+            resolved: None
+        }
+    }
+
+    // Re-resolve host if ttl expired. Previous addresses are kept and resolution is retried on failure
+    pub fn resolve(&mut self) -> io::Result<()> {
+        if let Some(resolved) = self.resolved {
+            if Instant::now().duration_since(resolved) < self.ttl {
+                return Ok(());
+            }
+        }
+        let addrs: Vec<SocketAddr> = self.host.to_socket_addrs()?.collect();
+        if addrs.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no addresses"));
+        }
+        self.resolved = Some(Instant::now());
+        self.addrs = addrs;
+        Ok(())
+    }
+
+    // First resolved address, or every address if resolve_all is set
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        if self.all {
+            self.addrs.clone()
+        } else {
+            self.addrs.iter().take(1).cloned().collect()
+        }
+    }
+
+    // Add resolved_ip label when probing every address
+    pub fn labels(&self, labels: &HashMap<String, String>, addr: &SocketAddr) -> HashMap<String, String> {
+        let mut result = labels.clone();
+        if self.all {
+            result.insert(String::from("resolved_ip"), addr.ip().to_string());
+        }
+        result
+    }
+
+    // Resolve and report failure as resolve_error probe
+    pub fn resolve_or_report(&mut self, name: &str, labels: &HashMap<String, String>, sender: &Sender<CheckResult>) {
+        if let Err(e) = self.resolve() {
+            error!("Failed to resolve {}: {}", self.host, e);
+            let mut to_emit = CheckResult{
+                name: String::from(name),
+                values: HashMap::new(),
+                processes: Vec::new(),
+                labels: labels.clone()};
+            to_emit.values.insert(String::from("resolve_error"), 1.0);
+            sender.send(to_emit).unwrap();
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::config::{ProbeConfig, get_duration};
+use yaml_rust::Yaml;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::thread;
+use std::time::{Duration, Instant};
+use rand::random;
+use log::debug;
+
+// Fixed rate ticks with per-probe phase offset, so probes don't fire together and don't drift
+pub struct Scheduler {
+    name: String,
+    interval: Duration,
+    next: Instant
+}
+
+impl Scheduler {
+    pub fn new(config: &ProbeConfig) -> Self {
+        let interval = config.interval;
+        let offset = match config.config.get("start_offset") {
+            Some(_) => get_duration(&config.config, "start_offset", Duration::ZERO),
+            None => {
+                let phase = config.config.get("phase")
+                    .unwrap_or(&Yaml::String(String::from("hash")))
+                    .clone()
+                    .into_string()
+                    .unwrap();
+                let seed = if phase == "random" {
+                    random::<u64>()
+                } else {
+                    let mut hasher = DefaultHasher::new();
+                    config.name.hash(&mut hasher);
+                    hasher.finish()
+                };
+                if interval.is_zero() {
+                    Duration::ZERO
+                } else {
+                    Duration::from_nanos(seed % interval.as_nanos() as u64)
+                }
+            }
+        };
+        debug!("Probe {} start offset {:?}", config.name, offset);
+        Self{
+            name: config.name.clone(),
+            interval,
+            next: Instant::now() + offset
+        }
+    }
+
+    // Time left until the next tick. Ticks missed by a slow probe are skipped
+    pub fn delay(&mut self) -> Duration {
+        let now = Instant::now();
+        let delay = self.next.saturating_duration_since(now);
+        if delay.is_zero() && !self.interval.is_zero() {
+            let missed = (now.duration_since(self.next).as_nanos() / self.interval.as_nanos()) as u32;
+            if missed > 0 {
+                debug!("Probe {} missed {} ticks", self.name, missed);
+            }
+            self.next += self.interval * (missed + 1);
+        } else {
+            self.next += self.interval;
+        }
+        delay
+    }
+
+    pub fn wait(&mut self) {
+        thread::sleep(self.delay());
+    }
+}
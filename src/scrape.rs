@@ -0,0 +1,375 @@
+use crate::config::ProbeConfig;
+use crate::tcp_connect::TcpConnector;
+use crate::resolver::Resolver;
+use crate::scheduler::Scheduler;
+use yaml_rust::Yaml;
+use regex::Regex;
+use std::sync::mpsc::Sender;
+use std::net::{SocketAddr, Shutdown};
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+use crate::checker::CheckResult;
+use std::collections::HashMap;
+use log::{debug, error};
+
+const MAX_RESPONSE_SIZE: usize = 64 * 1024 * 1024;
+
+pub struct ScrapeChecker {
+    connector: TcpConnector,
+    resolver: Resolver,
+    scheduler: Scheduler,
+    name: String,
+    host: String,
+    path: String,
+    precision: i64,
+    metrics: Vec<Regex>,
+    labels: HashMap<String, String>
+}
+
+struct Sample {
+    name: String,
+    labels: Vec<(String, String)>,
+    value: f32
+}
+
+// Body of chunked transfer encoding
+fn decode_chunked(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut result = Vec::new();
+    loop {
+        let line_end = data.windows(2).position(|w| w == b"\r\n")?;
+        let size_line = String::from_utf8_lossy(&data[..line_end]);
+        let size = usize::from_str_radix(size_line.split(';').next()?.trim(), 16).ok()?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Some(result);
+        }
+        result.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
+
+// Status code and body of HTTP response
+fn parse_response(response: &[u8]) -> Option<(u16, Vec<u8>)> {
+    let header_end = response.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let header = String::from_utf8_lossy(&response[..header_end]);
+    let body = &response[header_end + 4..];
+    let mut lines = header.lines();
+    let status = lines.next()?.split_whitespace().nth(1)?.parse::<u16>().ok()?;
+    let mut chunked = false;
+    let mut length = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            let name = name.trim().to_ascii_lowercase();
+            if name == "transfer-encoding" && value.to_ascii_lowercase().contains("chunked") {
+                chunked = true;
+            } else if name == "content-length" {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    if chunked {
+        return Some((status, decode_chunked(body)?));
+    }
+    match length {
+        Some(l) => Some((status, body.get(..l)?.to_vec())),
+        None => Some((status, body.to_vec()))
+    }
+}
+
+// name{label="value",...} value [timestamp]
+fn parse_sample(line: &str) -> Option<Sample> {
+    let name_end = line.find(|c: char| c == '{' || c.is_whitespace())?;
+    let name = String::from(&line[..name_end]);
+    let mut rest = &line[name_end..];
+    let mut labels = Vec::new();
+    if rest.starts_with('{') {
+        let mut chars = rest.char_indices().skip(1);
+        let mut label = String::new();
+        let mut value = String::new();
+        let mut in_value = false;
+        let mut end = None;
+        while let Some((i, c)) = chars.next() {
+            if in_value {
+                match c {
+                    '\\' => match chars.next()?.1 {
+                        'n' => value.push('\n'),
+                        other => value.push(other)
+                    },
+                    '"' => {
+                        labels.push((label.trim().to_string(), value.clone()));
+                        label.clear();
+                        value.clear();
+                        in_value = false;
+                    },
+                    _ => value.push(c)
+                }
+            } else {
+                match c {
+                    '}' => {
+                        end = Some(i);
+                        break;
+                    },
+                    '"' => in_value = true,
+                    '=' | ',' => {},
+                    _ => label.push(c)
+                }
+            }
+        }
+        rest = &rest[end? + 1..];
+    }
+    let value = match rest.split_whitespace().next()? {
+        "+Inf" => f32::INFINITY,
+        "-Inf" => f32::NEG_INFINITY,
+        v => v.parse::<f32>().ok()?
+    };
+    Some(Sample{name, labels, value})
+}
+
+// Samples of families matching any of metrics, every family if metrics is empty
+fn parse_metrics(body: &str, metrics: &[Regex]) -> Vec<Sample> {
+    let mut result = Vec::new();
+    let mut family = String::new();
+    let mut selected = metrics.is_empty();
+    for line in body.lines() {
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut fields = comment.split_whitespace();
+            if fields.next() == Some("TYPE") {
+                family = String::from(fields.next().unwrap_or_default());
+                selected = metrics.is_empty() || metrics.iter().any(|m| m.is_match(&family));
+            }
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let sample = match parse_sample(line) {
+            Some(s) => s,
+            None => {
+                debug!("Failed to parse metric {}", line);
+                continue;
+            }
+        };
+        // Samples without TYPE are families of their own
+        let in_family = !family.is_empty()
+            && (sample.name == family || sample.name.strip_prefix(&family).map(|s| s.starts_with('_')).unwrap_or(false));
+        let is_selected = if in_family {
+            selected
+        } else {
+            metrics.is_empty() || metrics.iter().any(|m| m.is_match(&sample.name))
+        };
+        if is_selected {
+            result.push(sample);
+        }
+    }
+    result
+}
+
+impl ScrapeChecker {
+    pub fn new(config: &ProbeConfig) -> Self {
+        let mut result = Self{
+            connector: TcpConnector::new(config),
+            name: config.name.clone(),
+            resolver: Resolver::new(config, config.host.clone()),
+            scheduler: Scheduler::new(config),
+            host: config.host.clone(),
+            path: config.config.get("path")
+                .unwrap_or(&Yaml::String(String::from("/metrics")))
+                .clone()
+                .into_string()
+                .unwrap(),
+            precision: config.config.get("precision")
+                .unwrap_or(&Yaml::Integer(1))
+                .clone()
+                .into_i64()
+                .unwrap(),
+            metrics: Vec::new(),
+            labels: config.labels.clone()
+        };
+        if let Some(yaml_rust::Yaml::Array(ref metrics)) = config.config.get("metrics") {
+            for metric in metrics {
+                let metric = metric.clone().into_string().unwrap();
+                match Regex::new(&format!("^(?:{})$", metric)) {
+                    Ok(r) => result.metrics.push(r),
+                    Err(e) => error!("Invalid metric regex {}: {}", metric, e)
+                }
+            }
+        }
+        result
+    }
+
+    fn emit(&self, sender: &Sender<CheckResult>, labels: HashMap<String, String>, value_name: String, value: f32) {
+        let mut to_emit = CheckResult{
+            name: self.name.clone(),
+            values: HashMap::new(),
+            processes: Vec::new(),
+            labels};
+        to_emit.values.insert(value_name, value);
+        sender.send(to_emit).unwrap();
+    }
+
+    fn to_time(&self, time: Duration) -> f32 {
+        (time.as_micros() as f32) / self.precision as f32
+    }
+
+    // Whole response of GET request, the server closes connection after it
+    fn fetch(&self, addr: &SocketAddr) -> Option<Vec<u8>> {
+        let (mut stream, _) = self.connector.connect(addr)?;
+        let deadline = Instant::now() + self.connector.timeout();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nAccept: text/plain;version=0.0.4\r\nConnection: close\r\n\r\n",
+            self.path, self.host);
+        stream.set_write_timeout(Some(self.connector.timeout())).unwrap();
+        if let Err(e) = stream.write_all(request.as_bytes()) {
+            debug!("Failed to send scrape request: {}", e);
+            return None;
+        }
+        let mut response = Vec::new();
+        let mut chunk = [0; 65536];
+        loop {
+            let now = Instant::now();
+            if now >= deadline || response.len() > MAX_RESPONSE_SIZE {
+                debug!("Scrape of {} timed out", addr);
+                return None;
+            }
+            stream.set_read_timeout(Some(deadline - now)).unwrap();
+            match stream.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(size) => response.extend_from_slice(&chunk[..size]),
+                Err(e) => {
+                    debug!("Failed to read scrape response: {}", e);
+                    return None;
+                }
+            }
+        }
+        _ = stream.shutdown(Shutdown::Both);
+        Some(response)
+    }
+
+    // Samples of selected metric families. Metric labels which clash with probe labels get exported_ prefix
+    fn emit_metrics(&self, sender: &Sender<CheckResult>, labels: &HashMap<String, String>, body: &str) {
+        for sample in parse_metrics(body, &self.metrics) {
+            let mut sample_labels = labels.clone();
+            for (label, value) in sample.labels {
+                if labels.contains_key(&label) {
+                    sample_labels.insert(format!("exported_{}", label), value);
+                } else {
+                    sample_labels.insert(label, value);
+                }
+            }
+            self.emit(sender, sample_labels, sample.name, sample.value);
+        }
+    }
+}
+
+pub fn scrape(mut checker: ScrapeChecker, sender: Sender<CheckResult>) {
+    loop {
+        checker.scheduler.wait();
+        checker.resolver.resolve_or_report(&checker.name, &checker.labels, &sender);
+        for addr in checker.resolver.addresses() {
+            let labels = checker.resolver.labels(&checker.labels, &addr);
+            let start = Instant::now();
+            let response = checker.fetch(&addr).and_then(|r| parse_response(&r));
+            let rtt = Instant::now().duration_since(start);
+            match response {
+                Some((200, body)) => {
+                    checker.emit(&sender, labels.clone(), String::from("scrape_rtt"), checker.to_time(rtt));
+                    checker.emit(&sender, labels.clone(), String::from("scrape_up"), 1.0);
+                    checker.emit_metrics(&sender, &labels, &String::from_utf8_lossy(&body));
+                },
+                Some((status, _)) => {
+                    debug!("Scrape of {} returned status {}", addr, status);
+                    checker.emit(&sender, labels, String::from("scrape_up"), 0.0);
+                },
+                None => {
+                    checker.emit(&sender, labels, String::from("scrape_up"), 0.0);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(sample: &Sample) -> Vec<(&str, &str)> {
+        sample.labels.iter().map(|(l, v)| (l.as_str(), v.as_str())).collect()
+    }
+
+    #[test]
+    fn escaped_label_values() {
+        let sample = parse_sample(r#"msg{path="C:\\dir",quote="say \"hi\"",text="a\nb",braces="}{,="} 1"#).unwrap();
+        assert_eq!(sample.name, "msg");
+        assert_eq!(labels(&sample), [
+            ("path", "C:\\dir"),
+            ("quote", "say \"hi\""),
+            ("text", "a\nb"),
+            ("braces", "}{,=")]);
+        assert_eq!(sample.value, 1.0);
+    }
+
+    #[test]
+    fn special_values() {
+        assert_eq!(parse_sample(r#"le_bucket{le="+Inf"} +Inf"#).unwrap().value, f32::INFINITY);
+        assert_eq!(parse_sample("low -Inf").unwrap().value, f32::NEG_INFINITY);
+        assert!(parse_sample("ratio NaN").unwrap().value.is_nan());
+        assert_eq!(parse_sample("small 1.5e-3").unwrap().value, 1.5e-3);
+        assert!(parse_sample("broken{a=\"b\" 1").is_none());
+        assert!(parse_sample("novalue").is_none());
+    }
+
+    #[test]
+    fn timestamp_is_ignored() {
+        let sample = parse_sample(r#"http_requests_total{code="200"} 1027 1395066363000"#).unwrap();
+        assert_eq!(labels(&sample), [("code", "200")]);
+        assert_eq!(sample.value, 1027.0);
+        assert_eq!(parse_sample("plain 3 1395066363000").unwrap().value, 3.0);
+    }
+
+    #[test]
+    fn family_selection() {
+        let body = "\
+# HELP rpc_duration_seconds RPC latency
+# TYPE rpc_duration_seconds histogram
+rpc_duration_seconds_bucket{le=\"0.1\"} 3
+rpc_duration_seconds_bucket{le=\"+Inf\"} 5
+rpc_duration_seconds_sum 0.7
+rpc_duration_seconds_count 5
+# TYPE process_open_fds gauge
+process_open_fds 12
+untyped_total 4
+rpc_untyped 1
+";
+        let metrics = [Regex::new("^(?:rpc_.*)$").unwrap()];
+        let names: Vec<String> = parse_metrics(body, &metrics).into_iter().map(|s| s.name).collect();
+        assert_eq!(names, [
+            "rpc_duration_seconds_bucket",
+            "rpc_duration_seconds_bucket",
+            "rpc_duration_seconds_sum",
+            "rpc_duration_seconds_count",
+            "rpc_untyped"]);
+        // Family without TYPE line is selected by the sample name
+        let metrics = [Regex::new("^(?:untyped_total)$").unwrap()];
+        let names: Vec<String> = parse_metrics(body, &metrics).into_iter().map(|s| s.name).collect();
+        assert_eq!(names, ["untyped_total"]);
+        assert_eq!(parse_metrics(body, &[]).len(), 7);
+    }
+
+    #[test]
+    fn chunked_response() {
+        let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+7;name=value\r\nup 1\nx \r\n3\r\n1\n\n\r\n0\r\n\r\n";
+        let (status, body) = parse_response(response).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body, b"up 1\nx 1\n\n");
+        assert!(decode_chunked(b"5\r\nab").is_none());
+    }
+
+    #[test]
+    fn content_length_response() {
+        let response = b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 4\r\n\r\ndown and more";
+        assert_eq!(parse_response(response), Some((503, b"down".to_vec())));
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort").is_none());
+    }
+}
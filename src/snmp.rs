@@ -0,0 +1,726 @@
+use crate::config::{ProbeConfig, get_duration};
+use crate::resolver::Resolver;
+use crate::scheduler::Scheduler;
+use crate::rate::RateTracker;
+use crate::ber::{self, Reader};
+use crate::usm::{AuthProtocol, PrivProtocol, AUTH_PARAMS_SIZE};
+use crate::interface::source_addr;
+use yaml_rust::Yaml;
+use std::sync::mpsc::Sender;
+use std::net::{SocketAddr, UdpSocket};
+use std::io;
+use std::process;
+use std::time::{Duration, Instant};
+use crate::checker::CheckResult;
+use std::collections::HashMap;
+use rand::random;
+use log::{debug, error};
+
+const GET_REQUEST: u8 = 0xa0;
+const RESPONSE: u8 = 0xa2;
+const GET_BULK_REQUEST: u8 = 0xa5;
+const REPORT: u8 = 0xa8;
+const MAX_MESSAGE_SIZE: i64 = 65507;
+// Stop walking runaway tables
+const MAX_WALK_SIZE: usize = 10000;
+const FLAG_AUTH: u8 = 0x01;
+const FLAG_PRIV: u8 = 0x02;
+const FLAG_REPORTABLE: u8 = 0x04;
+const USM_SECURITY_MODEL: i64 = 3;
+// usmStatsNotInTimeWindows and usmStatsUnknownEngineIDs reports
+const NOT_IN_TIME_WINDOWS: [u32; 10] = [1, 3, 6, 1, 6, 3, 15, 1, 1, 2];
+const UNKNOWN_ENGINE_IDS: [u32; 10] = [1, 3, 6, 1, 6, 3, 15, 1, 1, 4];
+
+struct OidValue {
+    name: String,
+    oid: Vec<u32>
+}
+
+struct Varbind {
+    oid: Vec<u32>,
+    tag: u8,
+    value: Vec<u8>
+}
+
+struct User {
+    name: String,
+    auth: Option<(AuthProtocol, String)>,
+    privacy: Option<(PrivProtocol, String)>,
+    context: String
+}
+
+// Authoritative engine of an agent, learned by discovery
+#[derive(Clone)]
+struct Engine {
+    id: Vec<u8>,
+    boots: u32,
+    time: u32,
+    synced: Instant,
+    auth_key: Vec<u8>,
+    priv_key: Vec<u8>
+}
+
+impl Engine {
+    fn time(&self) -> u32 {
+        self.time.saturating_add(self.synced.elapsed().as_secs() as u32)
+    }
+}
+
+struct V3Message<'a> {
+    id: i64,
+    flags: u8,
+    engine_id: &'a [u8],
+    boots: u32,
+    time: u32,
+    auth_params: &'a [u8],
+    priv_params: &'a [u8],
+    data: (u8, &'a [u8])
+}
+
+// PDU type, request id, error status and varbinds
+type Pdu = (u8, (i64, i64, Vec<Varbind>));
+
+enum V3Reply {
+    Varbinds(Vec<Varbind>),
+    Resync
+}
+
+pub struct SnmpChecker {
+    resolver: Resolver,
+    scheduler: Scheduler,
+    timeout: Duration,
+    name: String,
+    source: SocketAddr,
+    community: String,
+    user: Option<User>,
+    max_repetitions: i64,
+    get: Vec<OidValue>,
+    walk: Vec<OidValue>,
+    index_labels: Vec<(String, Vec<u32>)>,
+    labels: HashMap<String, String>,
+    engines: HashMap<SocketAddr, Engine>,
+    request_id: i32,
+    salt: u64,
+    rates: RateTracker
+}
+
+fn parse_oid(oid: &str) -> Option<Vec<u32>> {
+    oid.trim().trim_start_matches('.').split('.').map(|a| a.parse::<u32>().ok()).collect()
+}
+
+fn oid_to_string(oid: &[u32]) -> String {
+    oid.iter().map(|a| a.to_string()).collect::<Vec<String>>().join(".")
+}
+
+fn parse_oid_values(name: &str, config: Option<&Yaml>) -> Vec<OidValue> {
+    let mut result = Vec::new();
+    if let Some(yaml_rust::Yaml::Array(ref oids)) = config {
+        for oid in oids {
+            let oid_string = match oid["oid"].clone().into_string() {
+                Some(s) => s,
+                None => {
+                    error!("oid is required for every get and walk entry of {}", name);
+                    process::exit(1);
+                }
+            };
+            match parse_oid(&oid_string) {
+                Some(parsed) => result.push(OidValue{
+                    name: oid["name"].clone().into_string().unwrap_or(oid_string),
+                    oid: parsed
+                }),
+                None => error!("Invalid OID {}", oid_string)
+            }
+        }
+    }
+    result
+}
+
+fn encode_pdu(pdu_type: u8, request_id: i32, a: i64, b: i64, oids: &[Vec<u32>]) -> Vec<u8> {
+    let varbinds: Vec<Vec<u8>> = oids.iter()
+        .map(|oid| ber::encode_sequence(ber::SEQUENCE, &[ber::encode_oid(oid), ber::encode_null()]))
+        .collect();
+    ber::encode_sequence(pdu_type, &[
+        ber::encode_integer(request_id as i64),
+        ber::encode_integer(a),
+        ber::encode_integer(b),
+        ber::encode_sequence(ber::SEQUENCE, &varbinds)])
+}
+
+// Request id, error status and varbinds of a PDU
+fn decode_pdu(content: &[u8]) -> Option<(i64, i64, Vec<Varbind>)> {
+    let mut pdu = Reader::new(content);
+    let request_id = pdu.read_integer()?;
+    let error_status = pdu.read_integer()?;
+    pdu.read_integer()?;
+    let mut varbinds = Reader::new(pdu.read_tag(ber::SEQUENCE)?);
+    let mut result = Vec::new();
+    while !varbinds.is_empty() {
+        let mut varbind = Reader::new(varbinds.read_tag(ber::SEQUENCE)?);
+        let oid = ber::decode_oid(varbind.read_tag(ber::OBJECT_IDENTIFIER)?)?;
+        let (tag, value) = varbind.read()?;
+        result.push(Varbind{oid, tag, value: value.to_vec()});
+    }
+    Some((request_id, error_status, result))
+}
+
+fn encode_security(engine_id: &[u8], boots: u32, time: u32, user: &str, auth_params: &[u8], priv_params: &[u8]) -> Vec<u8> {
+    ber::encode_sequence(ber::SEQUENCE, &[
+        ber::encode_octets(engine_id),
+        ber::encode_integer(boots as i64),
+        ber::encode_integer(time as i64),
+        ber::encode_octets(user.as_bytes()),
+        ber::encode_octets(auth_params),
+        ber::encode_octets(priv_params)])
+}
+
+fn encode_v3(id: i32, flags: u8, security: &[u8], data: Vec<u8>) -> Vec<u8> {
+    ber::encode_sequence(ber::SEQUENCE, &[
+        ber::encode_integer(3),
+        ber::encode_sequence(ber::SEQUENCE, &[
+            ber::encode_integer(id as i64),
+            ber::encode_integer(MAX_MESSAGE_SIZE),
+            ber::encode_octets(&[flags]),
+            ber::encode_integer(USM_SECURITY_MODEL)]),
+        ber::encode_octets(security),
+        data])
+}
+
+fn parse_v3(message: &[u8]) -> Option<V3Message<'_>> {
+    let mut msg = Reader::new(Reader::new(message).read_tag(ber::SEQUENCE)?);
+    if msg.read_integer()? != 3 {
+        return None;
+    }
+    let mut header = Reader::new(msg.read_tag(ber::SEQUENCE)?);
+    let id = header.read_integer()?;
+    header.read_integer()?;
+    let flags = *header.read_tag(ber::OCTET_STRING)?.first()?;
+    let mut security = Reader::new(Reader::new(msg.read_tag(ber::OCTET_STRING)?).read_tag(ber::SEQUENCE)?);
+    Some(V3Message{
+        id,
+        flags,
+        engine_id: security.read_tag(ber::OCTET_STRING)?,
+        boots: security.read_integer()? as u32,
+        time: security.read_integer()? as u32,
+        auth_params: {
+            security.read_tag(ber::OCTET_STRING)?;
+            security.read_tag(ber::OCTET_STRING)?
+        },
+        priv_params: security.read_tag(ber::OCTET_STRING)?,
+        data: msg.read()?
+    })
+}
+
+// HMAC is calculated over the whole message with zeroed authentication parameters
+fn sign(auth: AuthProtocol, key: &[u8], message: &[u8], auth_params: &[u8]) -> Vec<u8> {
+    let offset = auth_params.as_ptr() as usize - message.as_ptr() as usize;
+    let mut zeroed = message.to_vec();
+    zeroed[offset..offset + auth_params.len()].fill(0);
+    auth.sign(key, &zeroed)
+}
+
+// Fill in authentication parameters of an encoded message
+fn sign_message(auth: AuthProtocol, key: &[u8], message: &mut [u8]) -> Option<()> {
+    let auth_params = parse_v3(message)?.auth_params;
+    let offset = auth_params.as_ptr() as usize - message.as_ptr() as usize;
+    let digest = sign(auth, key, message, auth_params);
+    message.get_mut(offset..offset + AUTH_PARAMS_SIZE)?.copy_from_slice(&digest);
+    Some(())
+}
+
+// Boots, time and PDU of a reply to request id sent with flags. Responses must be as secure
+// as the request, only reports (e.g. of a wrong time window) may come back unauthenticated
+fn parse_v3_reply(reply: &[u8], id: i32, flags: u8, user: &User, engine: &Engine) -> Option<(u32, u32, Pdu)> {
+    let parsed = parse_v3(reply)?;
+    if parsed.id != id as i64 {
+        return None;
+    }
+    if parsed.flags & FLAG_AUTH != 0 {
+        let (auth, _) = user.auth.as_ref()?;
+        if sign(*auth, &engine.auth_key, reply, parsed.auth_params) != parsed.auth_params {
+            debug!("Wrong digest of snmp reply");
+            return None;
+        }
+    }
+    let scoped = if parsed.flags & FLAG_PRIV != 0 {
+        let (privacy, _) = user.privacy.as_ref()?;
+        if parsed.data.0 != ber::OCTET_STRING {
+            return None;
+        }
+        privacy.decrypt(&engine.priv_key, parsed.boots, parsed.time, parsed.priv_params, parsed.data.1)?
+    } else {
+        if parsed.data.0 != ber::SEQUENCE {
+            return None;
+        }
+        ber::encode(ber::SEQUENCE, parsed.data.1)
+    };
+    let mut scoped = Reader::new(Reader::new(&scoped).read_tag(ber::SEQUENCE)?);
+    scoped.read_tag(ber::OCTET_STRING)?;
+    scoped.read_tag(ber::OCTET_STRING)?;
+    let (tag, pdu) = scoped.read()?;
+    let missing = flags & (FLAG_AUTH | FLAG_PRIV) & !parsed.flags;
+    if tag != REPORT && missing != 0 {
+        debug!("Snmp reply with lower security level than the request");
+        return None;
+    }
+    Some((parsed.boots, parsed.time, (tag, decode_pdu(pdu)?)))
+}
+
+fn numeric_value(varbind: &Varbind) -> Option<f64> {
+    match varbind.tag {
+        ber::INTEGER => ber::decode_integer(&varbind.value).map(|v| v as f64),
+        ber::COUNTER32 | ber::GAUGE32 | ber::TIMETICKS | ber::COUNTER64 => ber::decode_unsigned(&varbind.value).map(|v| v as f64),
+        // Some agents report numbers as strings
+        ber::OCTET_STRING => String::from_utf8_lossy(&varbind.value).trim().parse::<f64>().ok(),
+        _ => None
+    }
+}
+
+fn label_value(varbind: &Varbind) -> String {
+    match varbind.tag {
+        ber::OCTET_STRING => match String::from_utf8(varbind.value.clone()) {
+            Ok(s) if !s.chars().any(|c| c.is_control()) => s,
+            _ => varbind.value.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(":")
+        },
+        ber::IP_ADDRESS if varbind.value.len() == 4 => {
+            varbind.value.iter().map(|b| b.to_string()).collect::<Vec<String>>().join(".")
+        },
+        ber::OBJECT_IDENTIFIER => ber::decode_oid(&varbind.value).map(|o| oid_to_string(&o)).unwrap_or_default(),
+        _ => numeric_value(varbind).map(|v| v.to_string()).unwrap_or_default()
+    }
+}
+
+impl SnmpChecker {
+    pub fn new(config: &ProbeConfig) -> Self {
+        let version = match config.config.get("version") {
+            Some(Yaml::Integer(v)) => v.to_string(),
+            Some(Yaml::String(v)) => v.clone(),
+            _ => String::from("2c")
+        };
+        let get_string = |name: &str| config.config.get(name).map(|v| v.clone().into_string().unwrap());
+        let user = if version == "3" {
+            let auth = get_string("auth_protocol").map(|p| match AuthProtocol::parse(&p) {
+                Some(protocol) => (protocol, get_string("auth_password").unwrap_or_default()),
+                None => {
+                    error!("Unknown auth_protocol {} of {}", p, config.name);
+                    process::exit(1);
+                }
+            });
+            let privacy = get_string("priv_protocol").map(|p| match PrivProtocol::parse(&p) {
+                Some(protocol) => (protocol, get_string("priv_password").unwrap_or_default()),
+                None => {
+                    error!("Unknown priv_protocol {} of {}", p, config.name);
+                    process::exit(1);
+                }
+            });
+            if auth.is_none() && privacy.is_some() {
+                error!("priv_protocol of {} requires auth_protocol, privacy is disabled", config.name);
+            }
+            Some(User{
+                name: get_string("user").unwrap_or_default(),
+                privacy: if auth.is_some() { privacy } else { None },
+                auth,
+                context: get_string("context").unwrap_or_default()
+            })
+        } else {
+            None
+        };
+        let mut index_labels = Vec::new();
+        if let Some(yaml_rust::Yaml::Hash(ref labels)) = config.config.get("index_labels") {
+            for (label, oid) in labels {
+                let (label, oid) = match (label.clone().into_string(), oid.clone().into_string()) {
+                    (Some(l), Some(o)) => (l, o),
+                    _ => {
+                        error!("index_labels of {} should map label names to OIDs", config.name);
+                        process::exit(1);
+                    }
+                };
+                match parse_oid(&oid) {
+                    Some(parsed) => index_labels.push((label, parsed)),
+                    None => error!("Invalid OID {}", oid)
+                }
+            }
+        }
+        let source = get_string("source").unwrap_or(String::from("0.0.0.0:0"));
+        let source = match source_addr(&source) {
+            Some(addr) => addr,
+            None => {
+                error!("Invalid source {} of {}", source, config.name);
+                process::exit(1);
+            }
+        };
+        Self{
+            name: config.name.clone(),
+            resolver: Resolver::new(config, config.host.clone()),
+            scheduler: Scheduler::new(config),
+            timeout: get_duration(&config.config, "timeout", Duration::from_secs(1)),
+            source,
+            community: get_string("community").unwrap_or(String::from("public")),
+            user,
+            max_repetitions: config.config.get("max_repetitions")
+                .unwrap_or(&Yaml::Integer(10))
+                .clone()
+                .into_i64()
+                .unwrap(),
+            get: parse_oid_values(&config.name, config.config.get("get")),
+            walk: parse_oid_values(&config.name, config.config.get("walk")),
+            index_labels,
+            labels: config.labels.clone(),
+            engines: HashMap::new(),
+            request_id: random::<i32>() & 0x7fffffff,
+            salt: random::<u64>(),
+            rates: RateTracker::default()
+        }
+    }
+
+    fn emit(&self, sender: &Sender<CheckResult>, labels: &HashMap<String, String>, value_name: &str, value: f32) {
+        let mut to_emit = CheckResult{
+            name: self.name.clone(),
+            values: HashMap::new(),
+            processes: Vec::new(),
+            labels: labels.clone()};
+        to_emit.values.insert(String::from(value_name), value);
+        sender.send(to_emit).unwrap();
+    }
+
+    // Counters are emitted as rates per second from the second poll
+    fn emit_varbind(&mut self, sender: &Sender<CheckResult>, labels: &HashMap<String, String>, value_name: &str, key: &str, varbind: &Varbind, now: Instant) {
+        match varbind.tag {
+            ber::COUNTER32 | ber::COUNTER64 => {
+                let counter = match ber::decode_unsigned(&varbind.value) {
+                    Some(c) => c,
+                    None => return
+                };
+                let rate = if varbind.tag == ber::COUNTER32 {
+                    self.rates.update_counter32(key, counter as u32, now)
+                } else {
+                    self.rates.update(key, counter, now)
+                };
+                if let Some(rate) = rate {
+                    self.emit(sender, labels, value_name, rate);
+                }
+            },
+            ber::NO_SUCH_OBJECT | ber::NO_SUCH_INSTANCE | ber::END_OF_MIB_VIEW => {
+                debug!("No such object {}", oid_to_string(&varbind.oid));
+            },
+            _ => match numeric_value(varbind) {
+                Some(value) => self.emit(sender, labels, value_name, value as f32),
+                None => debug!("Value of {} is not a number", oid_to_string(&varbind.oid))
+            }
+        }
+    }
+
+    fn next_id(&mut self) -> i32 {
+        self.request_id = self.request_id.wrapping_add(1) & 0x7fffffff;
+        self.request_id
+    }
+
+    // Wait for a message accepted by parse, dropping late replies to previous requests
+    fn receive<T, F: FnMut(&[u8]) -> Option<T>>(&self, socket: &UdpSocket, mut parse: F) -> io::Result<T> {
+        let mut buffer = [0u8; 65536];
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(io::Error::from(io::ErrorKind::TimedOut));
+            }
+            socket.set_read_timeout(Some(left))?;
+            let size = socket.recv(&mut buffer)?;
+            match parse(&buffer[..size]) {
+                Some(result) => return Ok(result),
+                None => debug!("Unexpected snmp packet")
+            }
+        }
+    }
+
+    fn request_v2c(&mut self, socket: &UdpSocket, pdu_type: u8, a: i64, b: i64, oids: &[Vec<u32>]) -> io::Result<Vec<Varbind>> {
+        let id = self.next_id();
+        let message = ber::encode_sequence(ber::SEQUENCE, &[
+            ber::encode_integer(1),
+            ber::encode_octets(self.community.as_bytes()),
+            encode_pdu(pdu_type, id, a, b, oids)]);
+        socket.send(&message)?;
+        let (error_status, varbinds) = self.receive(socket, |reply| {
+            let mut msg = Reader::new(Reader::new(reply).read_tag(ber::SEQUENCE)?);
+            msg.read_integer()?;
+            msg.read_tag(ber::OCTET_STRING)?;
+            let (request_id, error_status, varbinds) = decode_pdu(msg.read_tag(RESPONSE)?)?;
+            if request_id != id as i64 {
+                return None;
+            }
+            Some((error_status, varbinds))
+        })?;
+        if error_status != 0 {
+            return Err(io::Error::other(format!("error status {}", error_status)));
+        }
+        Ok(varbinds)
+    }
+
+    // Learn engine id, boots and time of the agent from the report to an empty request
+    fn discover(&mut self, socket: &UdpSocket, addr: SocketAddr) -> io::Result<()> {
+        let id = self.next_id();
+        let scoped = ber::encode_sequence(ber::SEQUENCE, &[
+            ber::encode_octets(&[]),
+            ber::encode_octets(&[]),
+            encode_pdu(GET_REQUEST, id, 0, 0, &[])]);
+        socket.send(&encode_v3(id, FLAG_REPORTABLE, &encode_security(&[], 0, 0, "", &[], &[]), scoped))?;
+        let (engine_id, boots, time) = self.receive(socket, |reply| {
+            let reply = parse_v3(reply)?;
+            if reply.id != id as i64 || reply.engine_id.is_empty() {
+                return None;
+            }
+            Some((reply.engine_id.to_vec(), reply.boots, reply.time))
+        })?;
+        let user = self.user.as_ref().unwrap();
+        let (auth_key, priv_key) = match &user.auth {
+            Some((auth, auth_password)) => (
+                auth.localize_key(auth_password, &engine_id),
+                user.privacy.as_ref().map(|(_, p)| auth.localize_key(p, &engine_id)).unwrap_or_default()),
+            None => (Vec::new(), Vec::new())
+        };
+        debug!("Discovered snmp engine {:02x?} of {}", engine_id, addr);
+        self.engines.insert(addr, Engine{id: engine_id, boots, time, synced: Instant::now(), auth_key, priv_key});
+        Ok(())
+    }
+
+    fn request_v3(&mut self, socket: &UdpSocket, addr: SocketAddr, pdu_type: u8, a: i64, b: i64, oids: &[Vec<u32>]) -> io::Result<V3Reply> {
+        let id = self.next_id();
+        self.salt = self.salt.wrapping_add(1);
+        let salt = self.salt;
+        let engine = self.engines.get(&addr).unwrap().clone();
+        let user = self.user.as_ref().unwrap();
+        let time = engine.time();
+        let scoped = ber::encode_sequence(ber::SEQUENCE, &[
+            ber::encode_octets(&engine.id),
+            ber::encode_octets(user.context.as_bytes()),
+            encode_pdu(pdu_type, id, a, b, oids)]);
+        let mut flags = FLAG_REPORTABLE;
+        let mut auth_params = Vec::new();
+        if user.auth.is_some() {
+            flags |= FLAG_AUTH;
+            auth_params = vec![0; AUTH_PARAMS_SIZE];
+        }
+        let (data, priv_params) = match &user.privacy {
+            Some((privacy, _)) => {
+                flags |= FLAG_PRIV;
+                let (encrypted, priv_params) = privacy.encrypt(&engine.priv_key, engine.boots, time, salt, &scoped)
+                    .ok_or_else(|| io::Error::other(String::from("encryption failed")))?;
+                (ber::encode_octets(&encrypted), priv_params)
+            },
+            None => (scoped, Vec::new())
+        };
+        let security = encode_security(&engine.id, engine.boots, time, &user.name, &auth_params, &priv_params);
+        let mut message = encode_v3(id, flags, &security, data);
+        if let Some((auth, _)) = &user.auth {
+            sign_message(*auth, &engine.auth_key, &mut message).unwrap();
+        }
+        socket.send(&message)?;
+        let (boots, time, pdu) = self.receive(socket, |reply| parse_v3_reply(reply, id, flags, user, &engine))?;
+        let (tag, (_, error_status, varbinds)) = pdu;
+        if tag == REPORT {
+            let report = varbinds.first().map(|v| v.oid.clone()).unwrap_or_default();
+            if report.starts_with(&NOT_IN_TIME_WINDOWS) {
+                debug!("Snmp engine time of {} changed", addr);
+                if let Some(engine) = self.engines.get_mut(&addr) {
+                    engine.boots = boots;
+                    engine.time = time;
+                    engine.synced = Instant::now();
+                }
+                return Ok(V3Reply::Resync);
+            }
+            if report.starts_with(&UNKNOWN_ENGINE_IDS) {
+                self.engines.remove(&addr);
+                return Ok(V3Reply::Resync);
+            }
+            return Err(io::Error::other(format!("report {}", oid_to_string(&report))));
+        }
+        if tag != RESPONSE {
+            return Err(io::Error::other(format!("unexpected PDU {:#x}", tag)));
+        }
+        if error_status != 0 {
+            return Err(io::Error::other(format!("error status {}", error_status)));
+        }
+        Ok(V3Reply::Varbinds(varbinds))
+    }
+
+    fn request(&mut self, socket: &UdpSocket, addr: SocketAddr, pdu_type: u8, a: i64, b: i64, oids: &[Vec<u32>]) -> io::Result<Vec<Varbind>> {
+        if self.user.is_none() {
+            return self.request_v2c(socket, pdu_type, a, b, oids);
+        }
+        // Engine reboot or time drift costs one more round trip
+        for _ in 0..2 {
+            if !self.engines.contains_key(&addr) {
+                self.discover(socket, addr)?;
+            }
+            if let V3Reply::Varbinds(varbinds) = self.request_v3(socket, addr, pdu_type, a, b, oids)? {
+                return Ok(varbinds);
+            }
+        }
+        Err(io::Error::other(String::from("engine synchronization failed")))
+    }
+
+    // Rows of a table column with index as OID suffix
+    fn walk(&mut self, socket: &UdpSocket, addr: SocketAddr, root: &[u32]) -> io::Result<Vec<(Vec<u32>, Varbind)>> {
+        let mut result = Vec::new();
+        let mut current = root.to_vec();
+        loop {
+            let varbinds = self.request(socket, addr, GET_BULK_REQUEST, 0, self.max_repetitions, &[current.clone()])?;
+            if varbinds.is_empty() {
+                return Ok(result);
+            }
+            for varbind in varbinds {
+                if varbind.tag == ber::END_OF_MIB_VIEW || !varbind.oid.starts_with(root) || varbind.oid <= current {
+                    return Ok(result);
+                }
+                current = varbind.oid.clone();
+                result.push((varbind.oid[root.len()..].to_vec(), varbind));
+                if result.len() >= MAX_WALK_SIZE {
+                    debug!("Walk of {} is too long", oid_to_string(root));
+                    return Ok(result);
+                }
+            }
+        }
+    }
+
+    fn poll(&mut self, socket: &UdpSocket, addr: SocketAddr, labels: &HashMap<String, String>, sender: &Sender<CheckResult>) -> io::Result<()> {
+        socket.connect(addr)?;
+        if !self.get.is_empty() {
+            let oids: Vec<Vec<u32>> = self.get.iter().map(|g| g.oid.clone()).collect();
+            let varbinds = self.request(socket, addr, GET_REQUEST, 0, 0, &oids)?;
+            let now = Instant::now();
+            for (index, varbind) in varbinds.iter().enumerate() {
+                let value_name = match self.get.get(index) {
+                    Some(g) => g.name.clone(),
+                    None => continue
+                };
+                self.emit_varbind(sender, labels, &value_name, &format!("{}/{}", addr, value_name), varbind, now);
+            }
+        }
+        if self.walk.is_empty() {
+            return Ok(());
+        }
+        let mut index_labels = Vec::new();
+        for (label, oid) in self.index_labels.clone() {
+            let rows: HashMap<Vec<u32>, String> = self.walk(socket, addr, &oid)?
+                .into_iter()
+                .map(|(index, varbind)| (index, label_value(&varbind)))
+                .collect();
+            index_labels.push((label, rows));
+        }
+        for i in 0..self.walk.len() {
+            let (value_name, oid) = (self.walk[i].name.clone(), self.walk[i].oid.clone());
+            let rows = self.walk(socket, addr, &oid)?;
+            let now = Instant::now();
+            for (index, varbind) in rows {
+                let index_string = oid_to_string(&index);
+                let mut labels = labels.clone();
+                labels.insert(String::from("index"), index_string.clone());
+                for (label, rows) in &index_labels {
+                    if let Some(label_value) = rows.get(&index) {
+                        labels.insert(label.clone(), label_value.clone());
+                    }
+                }
+                self.emit_varbind(sender, &labels, &value_name, &format!("{}/{}/{}", addr, value_name, index_string), &varbind, now);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn snmp(mut checker: SnmpChecker, sender: Sender<CheckResult>) {
+    let socket = match UdpSocket::bind(checker.source) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Failed to bind {} of {}: {}", checker.source, checker.name, e);
+            process::exit(1);
+        }
+    };
+    socket.set_write_timeout(Some(checker.timeout)).unwrap();
+    loop {
+        checker.scheduler.wait();
+        checker.resolver.resolve_or_report(&checker.name, &checker.labels, &sender);
+        for addr in checker.resolver.addresses() {
+            let labels = checker.resolver.labels(&checker.labels, &addr);
+            match checker.poll(&socket, addr, &labels, &sender) {
+                Ok(()) => checker.emit(&sender, &labels, "loss", 0.0),
+                Err(e) => {
+                    debug!("Snmp poll of {} failed: {}", addr, e);
+                    checker.emit(&sender, &labels, "loss", 1.0);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENGINE_ID: [u8; 5] = [0x80, 0, 0x1f, 0x88, 0x04];
+    const ID: i32 = 42;
+
+    fn auth_user() -> (User, Engine) {
+        let user = User{
+            name: String::from("monitor"),
+            auth: Some((AuthProtocol::Sha, String::from("maplesyrup"))),
+            privacy: None,
+            context: String::new()
+        };
+        let engine = Engine{
+            id: ENGINE_ID.to_vec(),
+            boots: 1,
+            time: 100,
+            synced: Instant::now(),
+            auth_key: AuthProtocol::Sha.localize_key("maplesyrup", &ENGINE_ID),
+            priv_key: Vec::new()
+        };
+        (user, engine)
+    }
+
+    fn reply(pdu_type: u8, flags: u8, engine: &Engine) -> Vec<u8> {
+        let scoped = ber::encode_sequence(ber::SEQUENCE, &[
+            ber::encode_octets(&engine.id),
+            ber::encode_octets(&[]),
+            encode_pdu(pdu_type, ID, 0, 0, &[vec![1, 3, 6, 1, 2, 1, 1, 3, 0]])]);
+        let auth_params = if flags & FLAG_AUTH != 0 { vec![0; AUTH_PARAMS_SIZE] } else { Vec::new() };
+        let security = encode_security(&engine.id, engine.boots, engine.time, "monitor", &auth_params, &[]);
+        let mut message = encode_v3(ID, flags, &security, scoped);
+        if flags & FLAG_AUTH != 0 {
+            sign_message(AuthProtocol::Sha, &engine.auth_key, &mut message).unwrap();
+        }
+        message
+    }
+
+    #[test]
+    fn authenticated_response_is_accepted() {
+        let (user, engine) = auth_user();
+        let message = reply(RESPONSE, FLAG_AUTH, &engine);
+        let (_, _, (tag, (request_id, _, varbinds))) = parse_v3_reply(&message, ID, FLAG_AUTH | FLAG_REPORTABLE, &user, &engine).unwrap();
+        assert_eq!(tag, RESPONSE);
+        assert_eq!(request_id, ID as i64);
+        assert_eq!(varbinds.len(), 1);
+    }
+
+    #[test]
+    fn unauthenticated_response_is_rejected() {
+        let (user, engine) = auth_user();
+        let message = reply(RESPONSE, 0, &engine);
+        assert!(parse_v3_reply(&message, ID, FLAG_AUTH | FLAG_REPORTABLE, &user, &engine).is_none());
+    }
+
+    #[test]
+    fn unauthenticated_report_is_accepted() {
+        let (user, engine) = auth_user();
+        let message = reply(REPORT, 0, &engine);
+        let (_, _, (tag, _)) = parse_v3_reply(&message, ID, FLAG_AUTH | FLAG_REPORTABLE, &user, &engine).unwrap();
+        assert_eq!(tag, REPORT);
+    }
+
+    #[test]
+    fn tampered_response_is_rejected() {
+        let (user, engine) = auth_user();
+        let mut message = reply(RESPONSE, FLAG_AUTH, &engine);
+        let last = message.len() - 1;
+        message[last] ^= 1;
+        assert!(parse_v3_reply(&message, ID, FLAG_AUTH | FLAG_REPORTABLE, &user, &engine).is_none());
+    }
+}
@@ -1,6 +1,11 @@
 extern crate pnet;
 
-use crate::config::ProbeConfig;
+use crate::config::{ProbeConfig, get_strings};
+use crate::resolver::Resolver;
+use crate::scheduler::Scheduler;
+use crate::interface::{bind_to_device, probe_targets, source_ipv4s};
+use crate::timestamp;
+use crate::encap::Encap;
 use std::sync::{Arc, Mutex};
 use crate::checker::CheckResult;
 use log::{debug, error};
@@ -10,22 +15,22 @@ use std::time::{Duration, Instant};
 use std::sync::mpsc::Sender;
 use rand::random;
 use pnet::packet::ipv4;
-use std::net::{IpAddr, ToSocketAddrs};
-use std::thread;
+use std::net::IpAddr;
 use crate::pnet::packet::Packet;
-use pnet::transport::{transport_channel, tcp_packet_iter};
+use pnet::transport::transport_channel;
 use pnet::transport::TransportChannelType::{Layer3, Layer4};
 use pnet::transport::TransportProtocol::Ipv4;
 use pnet::packet::ip::IpNextHeaderProtocols;
-use pnet::packet::tcp::{MutableTcpPacket,TcpOption, ipv4_checksum};
-use pnet::util::checksum;
+use pnet::packet::tcp::{MutableTcpPacket, TcpPacket, TcpOption, ipv4_checksum};
 use std::collections::HashMap;
 
 pub struct SynChecker {
-    host: String,
+    resolver: Mutex<Resolver>,
     port: u16,
-    interval: i64,
-    source_ip: String,
+    scheduler: Mutex<Scheduler>,
+    source_ip: Vec<String>,
+    bind_interface: Option<String>,
+    encap: Option<Encap>,
     name: String,
     precision: i64,
     probes: Mutex<Vec<Probe>>,
@@ -34,16 +39,22 @@ pub struct SynChecker {
 
 struct Probe {
     seq: u32,
-    sent: Instant
+    addr: IpAddr,
+    labels: HashMap<String, String>,
+    sent: Instant,
+    sent_time: Duration,
+    sent_kernel: Option<Duration>
 }
 
 impl SynChecker {
     pub fn new(config: &ProbeConfig) -> Self {
         Self{
             name: config.name.clone(),
-            host: config.host.clone(),
-            interval: config.interval.clone(),
-            source_ip: config.config.get("source_ip").unwrap().clone().into_string().unwrap(),
+            resolver: Mutex::new(Resolver::new(config, format!("{}:0", config.host))),
+            scheduler: Mutex::new(Scheduler::new(config)),
+            source_ip: get_strings(&config.config, "source_ip"),
+            bind_interface: config.config.get("bind_interface").map(|s| s.clone().into_string().unwrap()),
+            encap: Encap::new(&config.config),
             probes: Mutex::new(Vec::<Probe>::new()),
             port: config.config.get("port").unwrap().clone().into_i64().unwrap() as u16,
             precision: config.config.get("precision")
@@ -56,135 +67,196 @@ impl SynChecker {
     }
 }
 
-pub fn syn_sender(checker: &Arc<SynChecker>) {
-    let mut host = checker.host.clone();
-    host.push_str(":0");
-    let mut source = checker.source_ip.clone();
-    source.push_str(":0");
-    let addr = match host.to_socket_addrs()
-        .unwrap()
-        .next()
-        .unwrap()
-        .ip() {
-            IpAddr::V4(ip) => ip,
-            IpAddr::V6(_) => {
-                return;
-            }
-        };
-    let saddr = match source.to_socket_addrs()
-        .unwrap()
-        .next()
-        .unwrap()
-        .ip() {
-            IpAddr::V4(ip) => ip,
-            IpAddr::V6(_) => {
+pub fn syn_sender(checker: &Arc<SynChecker>, sender: Sender<CheckResult>) {
+    let sources = match source_ipv4s(&checker.source_ip) {
+        Ok(s) => s,
+        Err(source_ip) => {
+            error!("Invalid source_ip {} for {}", source_ip, checker.name);
+            return;
+        }
+    };
+    let tunnel = match &checker.encap {
+        Some(encap) => match encap.tunnel(&checker.bind_interface) {
+            Ok(t) => Some(t),
+            Err(e) => {
+                error!("Failed to set up encapsulation for {}: {}", checker.name, e);
                 return;
             }
-        };
+        },
+        None => None
+    };
     let (mut tx, _) = transport_channel(4096, Layer3(IpNextHeaderProtocols::Tcp)).unwrap();
-    let payload = vec![0;1];
+    if let Some(interface) = &checker.bind_interface {
+        if let Err(e) = bind_to_device(tx.socket.fd, interface) {
+            error!("Failed to bind {} to {}: {}", checker.name, interface, e);
+            return;
+        }
+    }
+    if let Err(e) = timestamp::enable(tx.socket.fd) {
+        debug!("Kernel timestamps are not available: {}", e);
+    }
     let minimum_tcp_size = MutableTcpPacket::minimum_packet_size();
     let minimum_ip_size = ipv4::MutableIpv4Packet::minimum_packet_size() + minimum_tcp_size;
+    let packet_size = minimum_ip_size + 19;
     loop {
-        let mut ip_packet = vec![0; minimum_ip_size + 0 + 19];
-        let mut tcp_packet = vec![0; minimum_tcp_size + 0 + 19];
-        let mut tcp = MutableTcpPacket::new(&mut tcp_packet[..]).unwrap();
-        let seq = random::<u32>();
-        tcp.set_source(6535);
-        tcp.set_destination(checker.port);
-        tcp.set_sequence(seq);
-        tcp.set_acknowledgement(0); // TCP header acknowledgement number
-        tcp.set_data_offset(8); // TCP header data offset
-        tcp.set_reserved(0); // TCP header reserved
-        tcp.set_flags(2); // TCP header flags
-        tcp.set_window(64240); // TCP header window size
-        tcp.set_urgent_ptr(0); // TCP header urgent
-        tcp.set_options(&vec![TcpOption::mss(1460), TcpOption::sack_perm(), TcpOption::nop(), TcpOption::wscale(8)]);
-        tcp.set_checksum(ipv4_checksum(&tcp.to_immutable(), &saddr, &addr ));
-        let mut ip = ipv4::MutableIpv4Packet::new(&mut ip_packet[..]).unwrap();
-        ip.set_ttl(255);
-        ip.set_total_length((minimum_ip_size + payload.len()) as u16 + 40);
-        ip.set_next_level_protocol(IpNextHeaderProtocols::Tcp);
-        ip.set_header_length(5);
-        ip.set_version(4);
-        ip.set_flags(2);
-        ip.set_payload(&mut tcp_packet[..]);
-        ip.set_destination(addr);
-        ip.set_source(saddr);
-        ip.set_checksum(checksum(ip.packet(), 1));
-        match tx.send_to(ip, IpAddr::V4(addr)) {
-            Ok(_) => {
-                checker.probes.lock().unwrap().push(Probe{seq: seq+1, sent: Instant::now()});
-            },
-            Err(e) => {
-                error!("Error sending {:?}", e)
+        checker.scheduler.lock().unwrap().wait();
+        let mut resolver = checker.resolver.lock().unwrap();
+        resolver.resolve_or_report(&checker.name, &checker.labels, &sender);
+        for (addr, saddr, labels) in probe_targets(&checker.name, &resolver, &checker.labels, &sources, &checker.bind_interface, &sender) {
+            let mut ip_packet = vec![0; packet_size];
+            let mut tcp_packet = vec![0; minimum_tcp_size + 0 + 19];
+            let mut tcp = MutableTcpPacket::new(&mut tcp_packet[..]).unwrap();
+            let seq = random::<u32>();
+            tcp.set_source(6535);
+            tcp.set_destination(checker.port);
+            tcp.set_sequence(seq);
+            tcp.set_acknowledgement(0); // TCP header acknowledgement number
+            tcp.set_data_offset(8); // TCP header data offset
+            tcp.set_reserved(0); // TCP header reserved
+            tcp.set_flags(2); // TCP header flags
+            tcp.set_window(64240); // TCP header window size
+            tcp.set_urgent_ptr(0); // TCP header urgent
+            tcp.set_options(&vec![TcpOption::mss(1460), TcpOption::sack_perm(), TcpOption::nop(), TcpOption::wscale(8)]);
+            tcp.set_checksum(ipv4_checksum(&tcp.to_immutable(), &saddr, &addr ));
+            let mut ip = ipv4::MutableIpv4Packet::new(&mut ip_packet[..]).unwrap();
+            ip.set_ttl(255);
+            ip.set_total_length(packet_size as u16);
+            ip.set_next_level_protocol(IpNextHeaderProtocols::Tcp);
+            ip.set_header_length(5);
+            ip.set_version(4);
+            ip.set_flags(2);
+            ip.set_payload(&mut tcp_packet[..]);
+            ip.set_destination(addr);
+            ip.set_source(saddr);
+            // Header only, the kernel does not fix it up inside an encapsulated packet
+            ip.set_checksum(ipv4::checksum(&ip.to_immutable()));
+            // Register probe before sending, so a fast reply can't outrun it
+            let mut probes = checker.probes.lock().unwrap();
+            probes.push(Probe{
+                seq: seq.wrapping_add(1),
+                addr: IpAddr::V4(addr),
+                labels,
+                sent: Instant::now(),
+                sent_time: timestamp::now(),
+                sent_kernel: None});
+            let sent = match &tunnel {
+                Some(tunnel) => tx.send_to(
+                    ipv4::Ipv4Packet::new(&tunnel.wrap(ip.packet())).unwrap(),
+                    IpAddr::V4(tunnel.remote())),
+                None => tx.send_to(ip, IpAddr::V4(addr))
+            };
+            if let Err(e) = sent {
+                error!("Error sending {:?}", e);
+                probes.pop();
+            }
+            // Sent packet is at the end of error queue entry, after link layer header
+            for (packet, sent_kernel) in timestamp::sent(tx.socket.fd) {
+                if packet.len() < packet_size {
+                    continue;
+                }
+                let sent_seq = match ipv4::Ipv4Packet::new(&packet[packet.len() - packet_size..]) {
+                    Some(ip) => match TcpPacket::new(ip.payload()) {
+                        Some(tcp) => tcp.get_sequence().wrapping_add(1),
+                        None => continue
+                    },
+                    None => continue
+                };
+                for probe in probes.iter_mut() {
+                    if probe.seq == sent_seq {
+                        probe.sent_kernel = Some(sent_kernel);
+                    }
+                }
             }
         }
-        thread::sleep(Duration::from_secs(checker.interval as u64));
     }
 }
 
 pub fn syn_receiver(checker: &Arc<SynChecker>, sender: Sender<CheckResult>) {
-    let (_, mut rx) = transport_channel(4096, Layer4(Ipv4(IpNextHeaderProtocols::Tcp))).unwrap();
-    let mut host = checker.host.clone();
+    let (_, rx) = transport_channel(4096, Layer4(Ipv4(IpNextHeaderProtocols::Tcp))).unwrap();
+    if let Some(interface) = &checker.bind_interface {
+        if let Err(e) = bind_to_device(rx.socket.fd, interface) {
+            error!("Failed to bind {} to {}: {}", checker.name, interface, e);
+            return;
+        }
+    }
+    if let Err(e) = timestamp::enable(rx.socket.fd) {
+        debug!("Kernel timestamps are not available: {}", e);
+    }
     let timeout = Duration::new(1, 0);
-    host.push_str(":0");
-    let addr = host.to_socket_addrs()
-        .unwrap()
-        .next()
-        .unwrap()
-        .ip();
-    let mut iter = tcp_packet_iter(&mut rx);
+    let mut buffer = [0u8; 4096];
     loop {
-        match iter.next_with_timeout(timeout) {
-            Ok(result) => match result {
-                Some((packet, raddr)) => {
-                    if raddr == addr && packet.get_source() == checker.port && packet.get_destination() == 6535 {
-                        let now = Instant::now();
-                        let mut probes = checker.probes.lock().unwrap();
-                        for probe in 0..probes.len() {
-                            if probes[probe].seq == packet.get_acknowledgement() {
-                                let finished_probe = probes.swap_remove(probe);
-                                let mut to_emit = CheckResult{
-                                    name: checker.name.clone(),
-                                    values: HashMap::new(),
-                                    processes: Vec::new(),
-                                    labels: checker.labels.clone()};
-                                to_emit.values.insert(
-                                    String::from("rtt"),
-                                    (now.duration_since(finished_probe.sent).as_micros() as f32) / checker.precision as f32);
-                                sender.send(to_emit).unwrap();
-                                let mut to_emit = CheckResult{
-                                    name: checker.name.clone(),
-                                    values: HashMap::new(),
-                                    processes: Vec::new(),
-                                    labels: checker.labels.clone()};
-                                to_emit.values.insert(String::from("loss"), 0.0);
-                                sender.send(to_emit).unwrap();
-                                break;
+        match timestamp::recv(rx.socket.fd, &mut buffer, timeout) {
+            Ok(Some((size, received_kernel))) => {
+                let now = Instant::now();
+                let received_time = timestamp::now();
+                let ip = match ipv4::Ipv4Packet::new(&buffer[..size]) {
+                    Some(ip) => ip,
+                    None => continue
+                };
+                let raddr = IpAddr::V4(ip.get_source());
+                match TcpPacket::new(ip.payload()) {
+                    Some(packet) => {
+                        if packet.get_source() == checker.port && packet.get_destination() == 6535 {
+                            let mut probes = checker.probes.lock().unwrap();
+                            for probe in 0..probes.len() {
+                                if probes[probe].addr == raddr && probes[probe].seq == packet.get_acknowledgement() {
+                                    let finished_probe = probes.swap_remove(probe);
+                                    let rtt = received_kernel.unwrap_or(received_time)
+                                        .saturating_sub(finished_probe.sent_kernel.unwrap_or(finished_probe.sent_time));
+                                    let mut to_emit = CheckResult{
+                                        name: checker.name.clone(),
+                                        values: HashMap::new(),
+                                        processes: Vec::new(),
+                                        labels: finished_probe.labels.clone()};
+                                    to_emit.values.insert(
+                                        String::from("rtt"),
+                                        (rtt.as_micros() as f32) / checker.precision as f32);
+                                    sender.send(to_emit).unwrap();
+                                    let mut to_emit = CheckResult{
+                                        name: checker.name.clone(),
+                                        values: HashMap::new(),
+                                        processes: Vec::new(),
+                                        labels: finished_probe.labels.clone()};
+                                    to_emit.values.insert(
+                                        String::from("rtt_user"),
+                                        (now.duration_since(finished_probe.sent).as_micros() as f32) / checker.precision as f32);
+                                    sender.send(to_emit).unwrap();
+                                    let mut to_emit = CheckResult{
+                                        name: checker.name.clone(),
+                                        values: HashMap::new(),
+                                        processes: Vec::new(),
+                                        labels: finished_probe.labels};
+                                    to_emit.values.insert(String::from("loss"), 0.0);
+                                    sender.send(to_emit).unwrap();
+                                    break;
+                                }
                             }
                         }
+                    },
+                    None => {
+                        debug!("Error decoding tcp packet");
                     }
-                },
-                None => {
-                    debug!("Error decoding tcp packet");
                 }
             },
-            Err(_) => {
-                panic!("Error getting packet");
+            Ok(None) => {},
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted || e.kind() == std::io::ErrorKind::WouldBlock => {
+                debug!("Error getting packet {:?}", e);
+            },
+            Err(e) => {
+                panic!("Error getting packet {:?}", e);
             }
         }
         {
             let now = Instant::now();
             let mut probes = checker.probes.lock().unwrap();
-            for probe in probes.len()..0 {
-                if now.duration_since(probes[probe-1].sent) > timeout {
-                    probes.swap_remove(probe-1);
+            for probe in (0..probes.len()).rev() {
+                if now.duration_since(probes[probe].sent) > timeout {
+                    let finished_probe = probes.swap_remove(probe);
                     let mut to_emit = CheckResult{
                         name: checker.name.clone(),
                         values: HashMap::new(),
                         processes: Vec::new(),
-                        labels: checker.labels.clone()};
+                        labels: finished_probe.labels};
                     to_emit.values.insert(String::from("loss"), 1.0);
                     sender.send(to_emit).unwrap();
                 }
@@ -1,29 +1,130 @@
-use crate::config::ProbeConfig;
+use crate::config::{ProbeConfig, get_required_duration};
+use crate::resolver::Resolver;
+use crate::scheduler::Scheduler;
+use crate::interface::bind_to_device;
 use yaml_rust::Yaml;
 use std::sync::mpsc::Sender;
-use std::net::{TcpStream, Shutdown};
-use std::net::ToSocketAddrs;
-use std::thread;
+use std::net::{TcpStream, Shutdown, SocketAddr};
 use std::time::{Duration, Instant};
 use crate::checker::CheckResult;
 use std::collections::HashMap;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::mem;
+use socket2::{Domain, Socket, Type};
+use log::debug;
+
+// Head of struct tcp_info from linux/tcp.h, stable since 2.6
+#[repr(C)]
+#[derive(Default)]
+struct TcpInfo {
+    state: u8,
+    ca_state: u8,
+    retransmits: u8,
+    probes: u8,
+    backoff: u8,
+    options: u8,
+    wscale: u8,
+    flags: u8,
+    rto: u32,
+    ato: u32,
+    snd_mss: u32,
+    rcv_mss: u32,
+    unacked: u32,
+    sacked: u32,
+    lost: u32,
+    retrans: u32,
+    fackets: u32,
+    last_data_sent: u32,
+    last_ack_sent: u32,
+    last_data_recv: u32,
+    last_ack_recv: u32,
+    pmtu: u32,
+    rcv_ssthresh: u32,
+    rtt: u32,
+    rttvar: u32,
+    snd_ssthresh: u32,
+    snd_cwnd: u32,
+    advmss: u32,
+    reordering: u32,
+    rcv_rtt: u32,
+    rcv_space: u32,
+    total_retrans: u32
+}
+
+fn tcp_info(fd: RawFd) -> Option<TcpInfo> {
+    let mut info = TcpInfo::default();
+    let mut size = mem::size_of::<TcpInfo>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut TcpInfo as *mut libc::c_void,
+            &mut size)
+    };
+    if result < 0 {
+        debug!("Failed to get TCP_INFO: {}", std::io::Error::last_os_error());
+        return None;
+    }
+    Some(info)
+}
+
+// Connection setup shared with checks talking over the connection
+pub struct TcpConnector {
+    timeout: Duration,
+    bind_interface: Option<String>
+}
 
 pub struct TcpConnectChecker {
-    host: String,
-    interval: i64,
-    timeout: i64,
+    resolver: Resolver,
+    scheduler: Scheduler,
+    connector: TcpConnector,
     name: String,
     precision: i64,
     labels: HashMap<String, String>
 }
 
+impl TcpConnector {
+    pub fn new(config: &ProbeConfig) -> Self {
+        Self{
+            timeout: get_required_duration(&config.config, "timeout"),
+            bind_interface: config.config.get("bind_interface").map(|s| s.clone().into_string().unwrap())
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn connect(&self, addr: &SocketAddr) -> Option<(TcpStream, Duration)> {
+        let socket = match Socket::new(Domain::for_address(*addr), Type::STREAM, None) {
+            Ok(s) => s,
+            Err(e) => {
+                debug!("Failed to create socket: {}", e);
+                return None;
+            }
+        };
+        if let Some(interface) = &self.bind_interface {
+            if let Err(e) = bind_to_device(socket.as_raw_fd(), interface) {
+                debug!("Failed to bind to {}: {}", interface, e);
+                return None;
+            }
+        }
+        let start = Instant::now();
+        match socket.connect_timeout(&(*addr).into(), self.timeout()) {
+            Ok(_) => Some((socket.into(), Instant::now().duration_since(start))),
+            Err(_) => None
+        }
+    }
+}
+
 impl TcpConnectChecker {
     pub fn new(config: &ProbeConfig) -> Self {
         Self{
             name: config.name.clone(),
-            host: config.host.clone(),
-            interval: config.interval.clone(),
-            timeout: config.config.get("timeout").unwrap().clone().into_i64().unwrap(),
+            resolver: Resolver::new(config, config.host.clone()),
+            scheduler: Scheduler::new(config),
+            connector: TcpConnector::new(config),
             precision: config.config.get("precision")
                 .unwrap_or(&Yaml::Integer(1))
                 .clone()
@@ -32,41 +133,65 @@ impl TcpConnectChecker {
             labels: config.labels.clone()
         }
     }
-}
 
-pub fn tcp_connect(checker: TcpConnectChecker, sender: Sender<CheckResult>) {
-    let addr = checker.host.to_socket_addrs().unwrap().next().unwrap();
-    let timeout = Duration::from_secs(checker.timeout as u64);
-    loop {
-        let start = Instant::now();
-        if let Ok(stream) = TcpStream::connect_timeout(&addr, timeout) {
-            let mut rtt = CheckResult{
-                name: checker.name.clone(),
+    // Kernel smoothed rtt and path state of connected socket
+    fn emit_tcp_info(&self, sender: &Sender<CheckResult>, labels: &HashMap<String, String>, stream: &TcpStream) {
+        let info = match tcp_info(stream.as_raw_fd()) {
+            Some(i) => i,
+            None => return
+        };
+        let values = [
+            ("tcp_rtt", info.rtt as f32 / self.precision as f32),
+            ("tcp_rttvar", info.rttvar as f32 / self.precision as f32),
+            ("snd_cwnd", info.snd_cwnd as f32),
+            ("pmtu", info.pmtu as f32),
+            ("retransmits", info.total_retrans as f32)];
+        for (value_name, value) in values {
+            let mut to_emit = CheckResult{
+                name: self.name.clone(),
                 values: HashMap::new(),
                 processes: Vec::new(),
-                labels: checker.labels.clone()};
-            rtt.values.insert(
-                String::from("rtt"),
-                (Instant::now().duration_since(start).as_micros() as f32) / checker.precision as f32);
-            let mut loss = CheckResult{
-                name: checker.name.clone(),
-                values: HashMap::new(),
-                processes: Vec::new(),
-                labels: checker.labels.clone()};
-            loss.values.insert(String::from("loss"), 0.0);
-            sender.send(rtt).unwrap();
-            sender.send(loss).unwrap();
-            stream.shutdown(Shutdown::Both).unwrap();
-        } else {
-            let mut loss = CheckResult{
-                name: checker.name.clone(),
-                values: HashMap::new(),
-                processes: Vec::new(),
-                labels: checker.labels.clone()};
-            loss.values.insert(String::from("loss"), 1.0);
-            sender.send(loss).unwrap();
+                labels: labels.clone()};
+            to_emit.values.insert(String::from(value_name), value);
+            sender.send(to_emit).unwrap();
         }
+    }
+}
 
-        thread::sleep(Duration::from_secs(checker.interval as u64));
+pub fn tcp_connect(mut checker: TcpConnectChecker, sender: Sender<CheckResult>) {
+    loop {
+        checker.scheduler.wait();
+        checker.resolver.resolve_or_report(&checker.name, &checker.labels, &sender);
+        for addr in checker.resolver.addresses() {
+            let labels = checker.resolver.labels(&checker.labels, &addr);
+            if let Some((stream, connect_time)) = checker.connector.connect(&addr) {
+                let mut rtt = CheckResult{
+                    name: checker.name.clone(),
+                    values: HashMap::new(),
+                    processes: Vec::new(),
+                    labels: labels.clone()};
+                rtt.values.insert(
+                    String::from("rtt"),
+                    (connect_time.as_micros() as f32) / checker.precision as f32);
+                checker.emit_tcp_info(&sender, &labels, &stream);
+                let mut loss = CheckResult{
+                    name: checker.name.clone(),
+                    values: HashMap::new(),
+                    processes: Vec::new(),
+                    labels};
+                loss.values.insert(String::from("loss"), 0.0);
+                sender.send(rtt).unwrap();
+                sender.send(loss).unwrap();
+                _ = stream.shutdown(Shutdown::Both);
+            } else {
+                let mut loss = CheckResult{
+                    name: checker.name.clone(),
+                    values: HashMap::new(),
+                    processes: Vec::new(),
+                    labels};
+                loss.values.insert(String::from("loss"), 1.0);
+                sender.send(loss).unwrap();
+            }
+        }
     }
 }
@@ -0,0 +1,147 @@
+use crate::config::ProbeConfig;
+use crate::tcp_connect::TcpConnector;
+use crate::resolver::Resolver;
+use crate::scheduler::Scheduler;
+use yaml_rust::Yaml;
+use regex::bytes::Regex;
+use std::sync::mpsc::Sender;
+use std::net::{TcpStream, Shutdown};
+use std::process;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+use crate::checker::CheckResult;
+use std::collections::HashMap;
+use log::{debug, error};
+
+enum Step {
+    Send(Vec<u8>),
+    Expect(Regex)
+}
+
+pub struct TcpExpectChecker {
+    connector: TcpConnector,
+    resolver: Resolver,
+    scheduler: Scheduler,
+    name: String,
+    precision: i64,
+    steps: Vec<Step>,
+    labels: HashMap<String, String>
+}
+
+impl TcpExpectChecker {
+    pub fn new(config: &ProbeConfig) -> Self {
+        let mut result = Self{
+            connector: TcpConnector::new(config),
+            name: config.name.clone(),
+            resolver: Resolver::new(config, config.host.clone()),
+            scheduler: Scheduler::new(config),
+            precision: config.config.get("precision")
+                .unwrap_or(&Yaml::Integer(1))
+                .clone()
+                .into_i64()
+                .unwrap(),
+            steps: Vec::new(),
+            labels: config.labels.clone()
+        };
+        match config.config.get("steps") {
+            Some(yaml_rust::Yaml::Array(ref s)) => {
+                for step in s {
+                    if let Some(data) = step["send"].as_str() {
+                        result.steps.push(Step::Send(data.as_bytes().to_vec()));
+                    } else if let Some(expect) = step["expect"].as_str() {
+                        match Regex::new(expect) {
+                            Ok(r) => result.steps.push(Step::Expect(r)),
+                            Err(e) => {
+                                error!("Invalid expect regex {} of {}: {}", expect, config.name, e);
+                                process::exit(1);
+                            }
+                        }
+                    } else {
+                        error!("tcp_expect step should be send or expect");
+                    }
+                }
+            },
+            _ => {
+                error!("tcp_expect steps should be an array");
+            }
+        }
+        result
+    }
+
+    fn emit(&self, sender: &Sender<CheckResult>, labels: &HashMap<String, String>, step: Option<String>, value_name: &str, value: f32) {
+        let mut to_emit = CheckResult{
+            name: self.name.clone(),
+            values: HashMap::new(),
+            processes: Vec::new(),
+            labels: labels.clone()};
+        if let Some(s) = step {
+            to_emit.labels.insert(String::from("step"), s);
+        }
+        to_emit.values.insert(String::from(value_name), value);
+        sender.send(to_emit).unwrap();
+    }
+
+    fn to_time(&self, time: Duration) -> f32 {
+        (time.as_micros() as f32) / self.precision as f32
+    }
+}
+
+// Read until the buffer matches expect, then drop the matched part of the buffer
+fn expect(stream: &mut TcpStream, buffer: &mut Vec<u8>, expect: &Regex, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let mut chunk = [0; 4096];
+    loop {
+        if let Some(end) = expect.find(buffer).map(|m| m.end()) {
+            buffer.drain(..end);
+            return true;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        stream.set_read_timeout(Some(deadline - now)).unwrap();
+        match stream.read(&mut chunk) {
+            Ok(0) => return false,
+            Ok(size) => buffer.extend_from_slice(&chunk[..size]),
+            Err(_) => return false
+        }
+    }
+}
+
+pub fn tcp_expect(mut checker: TcpExpectChecker, sender: Sender<CheckResult>) {
+    let timeout = checker.connector.timeout();
+    loop {
+        checker.scheduler.wait();
+        checker.resolver.resolve_or_report(&checker.name, &checker.labels, &sender);
+        for addr in checker.resolver.addresses() {
+            let labels = checker.resolver.labels(&checker.labels, &addr);
+            let start = Instant::now();
+            if let Some((mut stream, connect_time)) = checker.connector.connect(&addr) {
+                checker.emit(&sender, &labels, Some(String::from("connect")), "rtt", checker.to_time(connect_time));
+                stream.set_write_timeout(Some(timeout)).unwrap();
+                let mut buffer = Vec::new();
+                let mut failed = false;
+                for (n, step) in checker.steps.iter().enumerate() {
+                    let step_start = Instant::now();
+                    let ok = match step {
+                        Step::Send(data) => stream.write_all(data).is_ok(),
+                        Step::Expect(regex) => expect(&mut stream, &mut buffer, regex, timeout)
+                    };
+                    if !ok {
+                        debug!("{} failed on step {}", checker.name, n);
+                        failed = true;
+                        break;
+                    }
+                    checker.emit(&sender, &labels, Some(format!("{}", n)), "rtt", checker.to_time(Instant::now().duration_since(step_start)));
+                }
+                if !failed {
+                    checker.emit(&sender, &labels, None, "total", checker.to_time(Instant::now().duration_since(start)));
+                }
+                checker.emit(&sender, &labels, None, "loss", if failed { 1.0 } else { 0.0 });
+                _ = stream.shutdown(Shutdown::Both);
+            } else {
+                checker.emit(&sender, &labels, None, "loss", 1.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Software timestamps are taken by the kernel when packet leaves the host or arrives to it,
+// so they don't include scheduling delays of the checker. Times are durations since epoch
+pub fn enable(fd: RawFd) -> io::Result<()> {
+    let flags: libc::c_uint = libc::SOF_TIMESTAMPING_TX_SOFTWARE
+        | libc::SOF_TIMESTAMPING_RX_SOFTWARE
+        | libc::SOF_TIMESTAMPING_SOFTWARE;
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            &flags as *const libc::c_uint as *const libc::c_void,
+            mem::size_of::<libc::c_uint>() as libc::socklen_t)
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// User space time in the same clock as kernel timestamps
+pub fn now() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+fn recvmsg(fd: RawFd, buffer: &mut [u8], flags: libc::c_int) -> io::Result<(usize, Option<Duration>)> {
+    let mut iov = libc::iovec{
+        iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buffer.len()
+    };
+    let mut control = [0u8; 512];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = control.len() as _;
+    let size = unsafe { libc::recvmsg(fd, &mut msg, flags) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut timestamp = None;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_TIMESTAMPING {
+                // Software timestamp is the first of three, the rest are hardware ones
+                let ts = (libc::CMSG_DATA(cmsg) as *const libc::timespec).read_unaligned();
+                if ts.tv_sec != 0 || ts.tv_nsec != 0 {
+                    timestamp = Some(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok((size as usize, timestamp))
+}
+
+// Receive packet with kernel receive timestamp. None if nothing arrived within timeout
+pub fn recv(fd: RawFd, buffer: &mut [u8], timeout: Duration) -> io::Result<Option<(usize, Option<Duration>)>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let mut pollfd = libc::pollfd{fd, events: libc::POLLIN, revents: 0};
+        let ready = unsafe { libc::poll(&mut pollfd, 1, remaining.as_millis() as libc::c_int) };
+        if ready < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if ready == 0 {
+            return Ok(None);
+        }
+        // POLLERR is reported for transmit timestamps queued after the caller drained them,
+        // drop them so poll doesn't keep waking up. A pending socket error is returned by recvmsg
+        if pollfd.revents & libc::POLLERR != 0 {
+            sent(fd);
+        }
+        match recvmsg(fd, buffer, libc::MSG_DONTWAIT) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+            result => return result.map(Some)
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+    }
+}
+
+// Drain transmit timestamps from the socket error queue. Every entry has a copy of the sent packet,
+// including link layer header
+pub fn sent(fd: RawFd) -> Vec<(Vec<u8>, Duration)> {
+    let mut result = Vec::new();
+    let mut buffer = [0u8; 2048];
+    while let Ok((size, timestamp)) = recvmsg(fd, &mut buffer, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT) {
+        if let Some(timestamp) = timestamp {
+            result.push((buffer[..size].to_vec(), timestamp));
+        }
+    }
+    result
+}
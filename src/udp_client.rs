@@ -1,19 +1,24 @@
-use crate::config::ProbeConfig;
+use crate::config::{ProbeConfig, get_strings, get_required_duration};
+use crate::resolver::Resolver;
+use crate::scheduler::Scheduler;
+use crate::interface::bind_to_device;
+use crate::timestamp;
 use yaml_rust::Yaml;
 use std::sync::mpsc::Sender;
 use std::net::UdpSocket;
-use std::thread;
 use std::time::{Duration, Instant};
 use crate::checker::CheckResult;
 use std::collections::HashMap;
-use log::debug;
+use std::os::unix::io::AsRawFd;
+use log::{debug, error};
 
 pub struct UdpClientChecker {
-    host: String,
-    interval: i64,
-    timeout: i64,
+    resolver: Resolver,
+    scheduler: Scheduler,
+    timeout: Duration,
     name: String,
-    source: String,
+    source: Vec<String>,
+    bind_interface: Option<String>,
     precision: i64,
     labels: HashMap<String, String>
 }
@@ -22,10 +27,11 @@ impl UdpClientChecker {
     pub fn new(config: &ProbeConfig) -> Self {
         Self{
             name: config.name.clone(),
-            host: config.host.clone(),
-            interval: config.interval.clone(),
-            timeout: config.config.get("timeout").unwrap().clone().into_i64().unwrap() as i64,
-            source: config.config.get("source").unwrap().clone().into_string().unwrap(),
+            resolver: Resolver::new(config, config.host.clone()),
+            scheduler: Scheduler::new(config),
+            timeout: get_required_duration(&config.config, "timeout"),
+            source: get_strings(&config.config, "source"),
+            bind_interface: config.config.get("bind_interface").map(|s| s.clone().into_string().unwrap()),
             precision: config.config.get("precision")
                 .unwrap_or(&Yaml::Integer(1))
                 .clone()
@@ -36,46 +42,102 @@ impl UdpClientChecker {
     }
 }
 
-pub fn udp_client(checker: UdpClientChecker, sender: Sender<CheckResult>) {
-    let socket = UdpSocket::bind(checker.source).unwrap();
-    socket.set_write_timeout(Some(Duration::from_secs(checker.timeout as u64))).unwrap();
-    socket.set_read_timeout(Some(Duration::from_secs(checker.timeout as u64))).unwrap();
-    socket.connect(checker.host).unwrap();
-    let interval = Duration::from_secs(checker.interval as u64);
+fn bind_socket(checker: &UdpClientChecker, source: &str) -> Option<UdpSocket> {
+    let socket = UdpSocket::bind(source).unwrap();
+    if let Some(interface) = &checker.bind_interface {
+        if let Err(e) = bind_to_device(socket.as_raw_fd(), interface) {
+            error!("Failed to bind {} to {}: {}", checker.name, interface, e);
+            return None;
+        }
+    }
+    socket.set_read_timeout(Some(checker.timeout)).unwrap();
+    socket.set_write_timeout(Some(checker.timeout)).unwrap();
+    if let Err(e) = timestamp::enable(socket.as_raw_fd()) {
+        debug!("Kernel timestamps are not available: {}", e);
+    }
+    Some(socket)
+}
+
+pub fn udp_client(mut checker: UdpClientChecker, sender: Sender<CheckResult>) {
+    // One socket per source, wildcard source if none is configured
+    let sources = if checker.source.is_empty() {
+        vec![String::from("0.0.0.0:0")]
+    } else {
+        checker.source.clone()
+    };
+    let mut sockets = Vec::new();
+    for source in &sources {
+        match bind_socket(&checker, source) {
+            Some(socket) => sockets.push(socket),
+            None => return
+        }
+    }
     //let mut buffer: &[u8] = &[0; 9600];
     let mut buffer = [0; 9600];
     loop {
-        let start = Instant::now();
-        if let Ok(_) = socket.send(&[0; 1]) {
-            if let Ok(_) = socket.recv(&mut buffer) {
-                let mut rtt = CheckResult{
-                    name: checker.name.clone(),
-                    values: HashMap::new(),
-                    processes: Vec::new(),
-                    labels: checker.labels.clone()};
-                rtt.values.insert(
-                    String::from("rtt"),
-                    (Instant::now().duration_since(start).as_micros() as f32) / checker.precision as f32);
-                let mut loss = CheckResult{
-                    name: checker.name.clone(),
-                    values: HashMap::new(),
-                    processes: Vec::new(),
-                    labels: checker.labels.clone()};
-                loss.values.insert(String::from("loss"), 0.0);
-                sender.send(rtt).unwrap();
-                sender.send(loss).unwrap();
+        checker.scheduler.wait();
+        checker.resolver.resolve_or_report(&checker.name, &checker.labels, &sender);
+        let targets = checker.resolver.addresses()
+            .into_iter()
+            .flat_map(|addr| sockets.iter().map(move |socket| (addr, socket)))
+            .collect::<Vec<_>>();
+        for (addr, socket) in targets {
+            let mut labels = checker.resolver.labels(&checker.labels, &addr);
+            if socket.connect(addr).is_err() {
+                debug!("Failed to connect to {}", addr);
+                continue;
+            }
+            if !checker.source.is_empty() {
+                if let Ok(local) = socket.local_addr() {
+                    labels.insert(String::from("source"), local.ip().to_string());
+                }
+            }
+            // Drop timestamps of previous probes
+            timestamp::sent(socket.as_raw_fd());
+            let start = Instant::now();
+            let start_time = timestamp::now();
+            if let Ok(_) = socket.send(&[0; 1]) {
+                let sent_kernel = timestamp::sent(socket.as_raw_fd()).pop().map(|(_, t)| t);
+                if let Ok(Some((_, received_kernel))) = timestamp::recv(socket.as_raw_fd(), &mut buffer, checker.timeout) {
+                    let now = Instant::now();
+                    let received_time = timestamp::now();
+                    let mut rtt = CheckResult{
+                        name: checker.name.clone(),
+                        values: HashMap::new(),
+                        processes: Vec::new(),
+                        labels: labels.clone()};
+                    rtt.values.insert(
+                        String::from("rtt"),
+                        (received_kernel.unwrap_or(received_time).saturating_sub(sent_kernel.unwrap_or(start_time)).as_micros() as f32) / checker.precision as f32);
+                    let mut rtt_user = CheckResult{
+                        name: checker.name.clone(),
+                        values: HashMap::new(),
+                        processes: Vec::new(),
+                        labels: labels.clone()};
+                    rtt_user.values.insert(
+                        String::from("rtt_user"),
+                        (now.duration_since(start).as_micros() as f32) / checker.precision as f32);
+                    let mut loss = CheckResult{
+                        name: checker.name.clone(),
+                        values: HashMap::new(),
+                        processes: Vec::new(),
+                        labels};
+                    loss.values.insert(String::from("loss"), 0.0);
+                    sender.send(rtt).unwrap();
+                    sender.send(rtt_user).unwrap();
+                    sender.send(loss).unwrap();
+                } else {
+                    let mut loss = CheckResult{
+                        name: checker.name.clone(),
+                        values: HashMap::new(),
+                        processes: Vec::new(),
+                        labels};
+                    loss.values.insert(String::from("loss"), 1.0);
+                    sender.send(loss).unwrap();
+                }
             } else {
-                let mut loss = CheckResult{
-                    name: checker.name.clone(),
-                    values: HashMap::new(),
-                    processes: Vec::new(),
-                    labels: checker.labels.clone()};
-                loss.values.insert(String::from("loss"), 1.0);
-                sender.send(loss).unwrap();
+                debug!("Failed to send probe");
             }
-        } else {
-            debug!("Failed to send probe");
         }
-        thread::sleep(interval);
     }
 }
@@ -0,0 +1,189 @@
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use sha1::{Digest, Sha1};
+use aes::Aes128;
+use aes::cipher::{AsyncStreamCipher, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use aes::cipher::block_padding::NoPadding;
+use des::Des;
+
+// SNMPv3 user based security model, RFC 3414 (HMAC-MD5-96, HMAC-SHA-96, CBC-DES) and RFC 3826 (CFB-AES-128)
+
+pub const AUTH_PARAMS_SIZE: usize = 12;
+// Passwords are stretched to 1MB before hashing
+const PASSWORD_STRETCH: usize = 1024 * 1024;
+
+#[derive(Clone, Copy)]
+pub enum AuthProtocol {
+    Md5,
+    Sha
+}
+
+#[derive(Clone, Copy)]
+pub enum PrivProtocol {
+    Des,
+    Aes
+}
+
+fn stretch(password: &str) -> Vec<u8> {
+    password.bytes().cycle().take(PASSWORD_STRETCH).collect()
+}
+
+impl AuthProtocol {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "md5" => Some(AuthProtocol::Md5),
+            "sha" | "sha1" => Some(AuthProtocol::Sha),
+            _ => None
+        }
+    }
+
+    // Key derived from the password alone, same for every engine
+    fn password_to_key(self, password: &str) -> Vec<u8> {
+        let stretched = stretch(password);
+        match self {
+            AuthProtocol::Md5 => Md5::digest(&stretched).to_vec(),
+            AuthProtocol::Sha => Sha1::digest(&stretched).to_vec()
+        }
+    }
+
+    // Key localized to the authoritative engine, used for authentication and privacy
+    pub fn localize_key(self, password: &str, engine_id: &[u8]) -> Vec<u8> {
+        if password.is_empty() {
+            return Vec::new();
+        }
+        let key = self.password_to_key(password);
+        let data = [&key[..], engine_id, &key[..]].concat();
+        match self {
+            AuthProtocol::Md5 => Md5::digest(&data).to_vec(),
+            AuthProtocol::Sha => Sha1::digest(&data).to_vec()
+        }
+    }
+
+    // Authentication parameters of a message with zeroed authentication parameters
+    pub fn sign(self, key: &[u8], message: &[u8]) -> Vec<u8> {
+        let mut digest = match self {
+            AuthProtocol::Md5 => {
+                let mut mac = Hmac::<Md5>::new_from_slice(key).unwrap();
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            },
+            AuthProtocol::Sha => {
+                let mut mac = Hmac::<Sha1>::new_from_slice(key).unwrap();
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+        };
+        digest.truncate(AUTH_PARAMS_SIZE);
+        digest
+    }
+}
+
+impl PrivProtocol {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "des" => Some(PrivProtocol::Des),
+            "aes" | "aes128" => Some(PrivProtocol::Aes),
+            _ => None
+        }
+    }
+
+    // Encrypted scoped PDU and privacy parameters. Salt must not repeat for the same key
+    pub fn encrypt(self, key: &[u8], boots: u32, time: u32, salt: u64, data: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+        match self {
+            PrivProtocol::Des => {
+                let params = [boots.to_be_bytes(), (salt as u32).to_be_bytes()].concat();
+                let iv: Vec<u8> = key.get(8..16)?.iter().zip(params.iter()).map(|(a, b)| a ^ b).collect();
+                let mut buffer = data.to_vec();
+                buffer.resize(data.len().div_ceil(8) * 8, 0);
+                let length = buffer.len();
+                cbc::Encryptor::<Des>::new_from_slices(key.get(..8)?, &iv).ok()?
+                    .encrypt_padded_mut::<NoPadding>(&mut buffer, length).ok()?;
+                Some((buffer, params))
+            },
+            PrivProtocol::Aes => {
+                let params = salt.to_be_bytes().to_vec();
+                let iv = [&boots.to_be_bytes()[..], &time.to_be_bytes()[..], &params[..]].concat();
+                let mut buffer = data.to_vec();
+                cfb_mode::Encryptor::<Aes128>::new_from_slices(key.get(..16)?, &iv).ok()?.encrypt(&mut buffer);
+                Some((buffer, params))
+            }
+        }
+    }
+
+    pub fn decrypt(self, key: &[u8], boots: u32, time: u32, params: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+        if params.len() != 8 {
+            return None;
+        }
+        let mut buffer = data.to_vec();
+        match self {
+            PrivProtocol::Des => {
+                if !buffer.len().is_multiple_of(8) {
+                    return None;
+                }
+                let iv: Vec<u8> = key.get(8..16)?.iter().zip(params.iter()).map(|(a, b)| a ^ b).collect();
+                cbc::Decryptor::<Des>::new_from_slices(key.get(..8)?, &iv).ok()?
+                    .decrypt_padded_mut::<NoPadding>(&mut buffer).ok()?;
+            },
+            PrivProtocol::Aes => {
+                let iv = [&boots.to_be_bytes()[..], &time.to_be_bytes()[..], params].concat();
+                cfb_mode::Decryptor::<Aes128>::new_from_slices(key.get(..16)?, &iv).ok()?.decrypt(&mut buffer);
+            }
+        }
+        Some(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 3414 A.3
+    const PASSWORD: &str = "maplesyrup";
+    const ENGINE_ID: [u8; 12] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+
+    #[test]
+    fn md5_password_to_key() {
+        assert_eq!(AuthProtocol::Md5.password_to_key(PASSWORD), [
+            0x9f, 0xaf, 0x32, 0x83, 0x88, 0x4e, 0x92, 0x83, 0x4e, 0xbc, 0x98, 0x47, 0xd8, 0xed, 0xd9, 0x63]);
+        assert_eq!(AuthProtocol::Md5.localize_key(PASSWORD, &ENGINE_ID), [
+            0x52, 0x6f, 0x5e, 0xed, 0x9f, 0xcc, 0xe2, 0x6f, 0x89, 0x64, 0xc2, 0x93, 0x07, 0x87, 0xd8, 0x2b]);
+    }
+
+    #[test]
+    fn sha_password_to_key() {
+        assert_eq!(AuthProtocol::Sha.password_to_key(PASSWORD), [
+            0x9f, 0xb5, 0xcc, 0x03, 0x81, 0x49, 0x7b, 0x37, 0x93, 0x52,
+            0x89, 0x39, 0xff, 0x78, 0x8d, 0x5d, 0x79, 0x14, 0x52, 0x11]);
+        assert_eq!(AuthProtocol::Sha.localize_key(PASSWORD, &ENGINE_ID), [
+            0x66, 0x95, 0xfe, 0xbc, 0x92, 0x88, 0xe3, 0x62, 0x82, 0x23,
+            0x5f, 0xc7, 0x15, 0x1f, 0x12, 0x84, 0x97, 0xb3, 0x8f, 0x3f]);
+    }
+
+    #[test]
+    fn empty_password_has_no_key() {
+        assert!(AuthProtocol::Md5.localize_key("", &ENGINE_ID).is_empty());
+    }
+
+    #[test]
+    fn sign_is_truncated_hmac() {
+        // RFC 2202 test case 2
+        let message = b"what do ya want for nothing?";
+        assert_eq!(AuthProtocol::Md5.sign(b"Jefe", message), [
+            0x75, 0x0c, 0x78, 0x3e, 0x6a, 0xb0, 0xb5, 0x03, 0xea, 0xa8, 0x6e, 0x31]);
+        assert_eq!(AuthProtocol::Sha.sign(b"Jefe", message), [
+            0xef, 0xfc, 0xdf, 0x6a, 0xe5, 0xeb, 0x2f, 0xa2, 0xd2, 0x74, 0x16, 0xd5]);
+    }
+
+    #[test]
+    fn privacy_round_trip() {
+        let key = AuthProtocol::Sha.localize_key(PASSWORD, &ENGINE_ID);
+        let data: Vec<u8> = (0..37).collect();
+        for protocol in [PrivProtocol::Des, PrivProtocol::Aes] {
+            let (encrypted, params) = protocol.encrypt(&key, 3, 1234, 0x0102030405060708, &data).unwrap();
+            assert_ne!(&encrypted[..data.len()], &data[..]);
+            let decrypted = protocol.decrypt(&key, 3, 1234, &params, &encrypted).unwrap();
+            // DES pads to the block size, BER length of the scoped PDU tells the padding apart
+            assert_eq!(&decrypted[..data.len()], &data[..]);
+        }
+    }
+}